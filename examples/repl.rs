@@ -1,8 +1,6 @@
-use pasm::{lexer::*, mnemonic::*, parser::*, token::*};
+use pasm::{diagnostic::Diagnostic, mnemonic::*, parser::*, Result};
 use std::io::{stdout, Write};
 
-use lalrpop_util::ParseError;
-
 fn main() -> std::io::Result<()> {
     loop {
         print!(">> ");
@@ -15,10 +13,7 @@ fn main() -> std::io::Result<()> {
             "quit" | ":q" => break,
             input => match parse(input) {
                 Ok(res) => println!("{}", &res),
-                Err(err) => {
-                    println!("Parse Error");
-                    println!("{:#?}", &err);
-                }
+                Err(err) => print!("{}", Diagnostic::from(&err).render("<stdin>", input, false)),
             },
         }
     }
@@ -26,7 +21,6 @@ fn main() -> std::io::Result<()> {
     Ok(())
 }
 
-fn parse(input: &str) -> Result<Item, ParseError<(), Token, ()>> {
-    let lexer = Lexer::new(input);
-    poco::ItemParser::new().parse(lexer)
+fn parse(input: &str) -> Result<Item> {
+    parse_item(input).map(|item| item.node)
 }
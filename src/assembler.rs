@@ -1,44 +1,864 @@
-use crate::{code::*, mnemonic::*};
-use std::collections::HashMap;
+use crate::{
+    code::*,
+    error::{Error, Result, Warning},
+    expr::{Expr, UnaryOp},
+    mnemonic::*,
+    register::Register,
+    span::{Span, Spanned},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+};
+
+/// Maps each label to the address of the instruction that follows it.
+pub type LabelTable = HashMap<String, u16>;
+
+/// Maps each symbol defined with `.equ` or `.set` to its final value.
+pub type SymbolTable = HashMap<String, i64>;
+
+/// An item together with the address it is placed at.
+pub type Placed<T> = (u16, Spanned<T>);
+
+/// Lays out `input`, returning the mnemonics with their addresses and their
+/// symbols substituted. Pseudo-instructions are replaced with the
+/// instructions they expand to.
+pub fn convert<I>(input: I) -> Result<(Vec<Placed<Mnemonic>>, LabelTable, SymbolTable)>
+where
+    I: IntoIterator,
+    I::Item: Into<Spanned<Item>>,
+{
+    convert_with(input, AssembleOptions::default())
+}
+
+pub fn convert_with<I>(
+    input: I,
+    options: AssembleOptions,
+) -> Result<(Vec<Placed<Mnemonic>>, LabelTable, SymbolTable)>
+where
+    I: IntoIterator,
+    I::Item: Into<Spanned<Item>>,
+{
+    let layout = layout(input, options);
+
+    if layout.errors.is_empty() {
+        Ok((layout.mnemonics, layout.table, layout.symbols))
+    } else {
+        Err(Error::from_errors(layout.errors))
+    }
+}
+
+/// Options that change how the program is assembled.
+#[derive(Debug, Clone, Copy)]
+pub struct AssembleOptions {
+    /// The word that fills the gaps `.org` and `.align` leave in the
+    /// instruction memory. Defaults to 0, the encoding of `NOP`.
+    pub pad: u16,
+
+    /// The register `PUSH` and `POP` use as the stack pointer. Defaults to
+    /// `r6`, since `r7` holds the return address of `CALL`.
+    pub stack_pointer: Register,
+
+    /// Rewrites the branches and jumps whose target is out of range into
+    /// longer sequences when set. A jump beyond the reach of `JMP` loads its
+    /// target into this register, which must not hold a live value there.
+    pub relax: Option<Register>,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        AssembleOptions {
+            pad: 0,
+            stack_pointer: Register::R6,
+            relax: None,
+        }
+    }
+}
+
+/// Converts and assembles `input`, reporting the errors of both steps at once.
+pub fn assemble_items<I>(input: I) -> Result<Program>
+where
+    I: IntoIterator,
+    I::Item: Into<Spanned<Item>>,
+{
+    assemble_items_with(input, AssembleOptions::default())
+}
+
+pub fn assemble_items_with<I>(input: I, options: AssembleOptions) -> Result<Program>
+where
+    I: IntoIterator,
+    I::Item: Into<Spanned<Item>>,
+{
+    let Layout {
+        mnemonics,
+        expansions,
+        data,
+        table,
+        data_labels,
+        mut errors,
+        ..
+    } = layout(input, options);
+
+    let addresses: Vec<u16> = mnemonics.iter().map(|(addr, _)| *addr).collect();
+    let codes = match assemble(mnemonics, &table) {
+        Ok(codes) => {
+            let codes =
+                codes
+                    .into_iter()
+                    .zip(expansions)
+                    .map(|(code, expansion)| match expansion {
+                        Some(expansion) => code.expanded_from(expansion),
+                        None => code,
+                    });
+            fill(addresses.into_iter().zip(codes), Code::pad(options.pad))
+        }
+        Err(err) => {
+            errors.extend(err.into_errors());
+            Vec::new()
+        }
+    };
 
-pub fn convert<I>(input: I) -> (Vec<Mnemonic>, HashMap<String, i16>)
+    let data: Vec<(u16, Data)> = data
+        .iter()
+        .filter_map(|(addr, datum)| {
+            encode_data(datum)
+                .map(|data| (*addr, data))
+                .map_err(|err| errors.push(err))
+                .ok()
+        })
+        .collect();
+
+    if errors.is_empty() {
+        let data = fill(data, Data::new(0, Span::default()));
+        let (data_labels, labels) = table
+            .into_iter()
+            .partition(|(label, _)| data_labels.contains(label));
+        return Ok(Program {
+            codes,
+            data,
+            labels,
+            data_labels,
+        });
+    }
+
+    errors.sort_by_key(|err| err.span().map(|span| span.start));
+    Err(Error::from_errors(errors))
+}
+
+/// Assembles the items recovered from a source that has `parse_errors`,
+/// reporting those errors together with the ones the assembler finds, in
+/// source order, so that one run shows every problem.
+pub fn assemble_recovered<I>(
+    input: I,
+    parse_errors: Vec<Error>,
+    options: AssembleOptions,
+) -> Result<Program>
 where
-    I: IntoIterator<Item = Item>,
+    I: IntoIterator,
+    I::Item: Into<Spanned<Item>>,
 {
+    let result = assemble_items_with(input, options);
+    if parse_errors.is_empty() {
+        return result;
+    }
+
+    let mut errors = parse_errors;
+    if let Err(err) = result {
+        errors.extend(err.into_errors());
+    }
+    errors.sort_by_key(|err| err.span().map(|span| span.start));
+    Err(Error::from_errors(errors))
+}
+
+/// Builds a memory image from words and their addresses, filling the gaps
+/// with `pad`.
+fn fill<T: Clone>(words: impl IntoIterator<Item = (u16, T)>, pad: T) -> Vec<T> {
+    let mut image = Vec::new();
+    for (addr, word) in words {
+        let addr = usize::from(addr);
+        if image.len() <= addr {
+            image.resize(addr + 1, pad.clone());
+        }
+        image[addr] = word;
+    }
+
+    image
+}
+
+/// A program laid out in memory, with its symbols substituted where known.
+struct Layout {
+    mnemonics: Vec<Placed<Mnemonic>>,
+
+    /// The pseudo-instruction or relaxed branch each mnemonic was expanded
+    /// from, if any.
+    expansions: Vec<Option<Expansion>>,
+
+    /// The instruction each mnemonic was written as: the index of its item
+    /// and its position in the expansion of a pseudo-instruction.
+    origins: Vec<(usize, usize)>,
+    data: Vec<Placed<Datum>>,
+    table: LabelTable,
+
+    /// The labels of `table` defined in the data section.
+    data_labels: HashSet<String>,
+    symbols: SymbolTable,
+    errors: Vec<Error>,
+}
+
+/// A word of the data section before it is evaluated.
+#[derive(Debug, Clone)]
+struct Datum {
+    value: Expr,
+    range: RangeInclusive<i64>,
+}
+
+impl Datum {
+    /// A word that holds any 16-bit value, signed or unsigned.
+    fn word(value: Expr) -> Self {
+        Datum {
+            value,
+            range: i64::from(i16::MIN)..=i64::from(u16::MAX),
+        }
+    }
+
+    /// A word that holds any 8-bit value, signed or unsigned.
+    fn byte(value: Expr) -> Self {
+        Datum {
+            value,
+            range: i64::from(i8::MIN)..=i64::from(u8::MAX),
+        }
+    }
+}
+
+/// The number of words in each memory, which Poco addresses with 16 bits.
+const MEMORY_SIZE: u32 = 1 << 16;
+
+/// The next free address of a section, and the addresses already used in it.
+struct Cursor {
+    section: Section,
+
+    /// The next free address, or `MEMORY_SIZE` once the last one is taken.
+    addr: u32,
+    used: HashMap<u16, Span>,
+
+    /// Whether an overlap has been reported since the last `.org` or
+    /// `.align`, so that a region is only reported once.
+    overlapping: bool,
+
+    /// Whether the region has been reported to run past the end of the
+    /// memory.
+    overflowing: bool,
+}
+
+impl Cursor {
+    fn new(section: Section) -> Self {
+        Cursor {
+            section,
+            addr: 0,
+            used: HashMap::new(),
+            overlapping: false,
+            overflowing: false,
+        }
+    }
+
+    /// The address a label defined here refers to. A label after the last
+    /// word of the memory wraps around to 0, like the program counter.
+    fn label(&self) -> u16 {
+        self.addr as u16
+    }
+
+    /// Takes the next address for the item at `span`.
+    fn place(&mut self, span: Span, errors: &mut Vec<Error>) -> u16 {
+        if self.addr >= MEMORY_SIZE {
+            if !self.overflowing {
+                errors.push(Error::AddressOverflow {
+                    section: self.section,
+                    span,
+                });
+                self.overflowing = true;
+            }
+            return u16::MAX;
+        }
+
+        let addr = self.addr as u16;
+        match self.used.get(&addr) {
+            Some(previous) if !self.overlapping => {
+                errors.push(Error::Overlap {
+                    address: addr,
+                    span,
+                    previous: *previous,
+                });
+                self.overlapping = true;
+            }
+            Some(_) => {}
+            None => {
+                self.used.insert(addr, span);
+            }
+        }
+
+        self.addr += 1;
+        addr
+    }
+
+    /// Starts a new region at `addr`.
+    fn move_to(&mut self, addr: u32) {
+        self.addr = addr;
+        self.overlapping = false;
+        self.overflowing = false;
+    }
+}
+
+/// Assigns an address to every mnemonic and data word, collecting the labels
+/// and symbols into tables.
+///
+/// Instructions and data have separate address spaces; a label takes the
+/// address in the section it is defined in. Each section starts at address
+/// 0 and can be moved with `.org` and `.align`.
+///
+/// With `options.relax`, the program is laid out again with the branches
+/// and jumps that do not reach their targets lengthened, until every
+/// target is in range or cannot be reached by any longer sequence. Since
+/// instructions only ever grow, the addresses converge.
+fn layout<I>(input: I, options: AssembleOptions) -> Layout
+where
+    I: IntoIterator,
+    I::Item: Into<Spanned<Item>>,
+{
+    let mut items: Vec<Spanned<Item>> = input.into_iter().map(Into::into).collect();
+    scope_labels(&mut items);
+
+    let mut reaches = HashMap::new();
+    loop {
+        let layout = layout_once(&items, options, &reaches);
+        if options.relax.is_none() || !lengthen(&layout, &mut reaches) {
+            return layout;
+        }
+    }
+}
+
+/// Gives local and anonymous labels names that are unique in the program,
+/// so that they can share the label table with the global labels.
+///
+/// A local label `:.loop` belongs to the last global label before it, say
+/// `outer`, and becomes `outer.loop`; a reference to `.loop` means the one
+/// of the current global label. An anonymous label `:1` may be defined any
+/// number of times; `1b` refers to the nearest one before the reference and
+/// `1f` to the nearest one after.
+fn scope_labels(items: &mut [Spanned<Item>]) {
+    let mut anonymous: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        match &item.node {
+            Item::Label(label) if is_anonymous(label) => {
+                anonymous.entry(label.clone()).or_default().push(index)
+            }
+            _ => {}
+        }
+    }
+    // Users cannot write a label with `@`, and macros only add it to names
+    // that start with a letter.
+    let anonymous_name = |label: &str, nth: usize| format!("{}@{}", label, nth);
+
+    let mut scope = String::new();
+    for (index, item) in items.iter_mut().enumerate() {
+        if let Item::Label(label) = &mut item.node {
+            if is_anonymous(label) {
+                let nth = anonymous[label.as_str()]
+                    .iter()
+                    .position(|&position| position == index)
+                    .unwrap();
+                *label = anonymous_name(label, nth);
+            } else if label.starts_with('.') {
+                *label = format!("{}{}", scope, label);
+            } else {
+                scope = label.clone();
+            }
+            continue;
+        }
+
+        // A reference that cannot be resolved is reported by its own name.
+        item.node.rename_references(&|name: &str| {
+            if name.starts_with('.') {
+                return Some(format!("{}{}", scope, name));
+            }
+
+            let (label, direction) = name.split_at(name.len() - 1);
+            let positions = anonymous.get(label)?;
+            let nth = match direction {
+                "b" => positions.iter().rposition(|&position| position < index)?,
+                "f" => positions.iter().position(|&position| position > index)?,
+                _ => return None,
+            };
+            Some(anonymous_name(label, nth))
+        });
+    }
+}
+
+/// Whether `label` names an anonymous label: `1`
+fn is_anonymous(label: &str) -> bool {
+    !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// How far a relaxed branch or jump reaches.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Reach {
+    /// A branch is inverted to skip over a `JMP` to the target.
+    Jump,
+
+    /// The target is loaded into a register and jumped to with `JR`, or
+    /// `JALR` for `JAL`.
+    Register,
+}
+
+/// Lengthens the branches and jumps of `layout` whose targets are out of
+/// range, returning whether any changed.
+fn lengthen(layout: &Layout, reaches: &mut HashMap<(usize, usize), Reach>) -> bool {
+    let mut changed = false;
+    for ((addr, m), origin) in layout.mnemonics.iter().zip(&layout.origins) {
+        let (label, bits, reach) = match &m.node {
+            Mnemonic::B(instr) => (&instr.label, 8, Reach::Jump),
+            Mnemonic::J(instr) => (&instr.label, 11, Reach::Register),
+            _ => continue,
+        };
+
+        let in_range = match layout.table.get(label) {
+            Some(&target) => displacement(label, target, *addr, bits, m.span).is_ok(),
+            None => true,
+        };
+        if !in_range && reaches.get(origin).copied() < Some(reach) {
+            reaches.insert(*origin, reach);
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+/// Returns the instructions that take `m` to its target at `reach`, and
+/// the label that the inverted branch skips to. `addr` is the address of
+/// the first instruction.
+fn relax(
+    m: Mnemonic,
+    reach: Reach,
+    scratch: Register,
+    addr: u16,
+) -> (Vec<Mnemonic>, Option<String>) {
+    let jump = |opcode: OpcodeJ, label: String| match reach {
+        Reach::Jump => vec![Mnemonic::instr_j(opcode, label)],
+        Reach::Register => {
+            let (reg, funct) = match opcode {
+                OpcodeJ::JMP => (scratch, FunctJR::JR),
+                OpcodeJ::JAL => (LINK_REGISTER, FunctJR::JALR),
+            };
+            let target = Expr::Symbol(label);
+            vec![
+                Mnemonic::instr_i(OpcodeI::LDHI, reg, Expr::unary(UnaryOp::Hi, target.clone())),
+                Mnemonic::instr_i(OpcodeI::ADDIU, reg, Expr::unary(UnaryOp::Lo, target)),
+                Mnemonic::instr_jr(funct, reg),
+            ]
+        }
+    };
+
+    match m {
+        Mnemonic::B(instr) => {
+            let rest = jump(OpcodeJ::JMP, instr.label);
+            // Users cannot write a label starting with `@`.
+            let skip = format!("@{}", addr.wrapping_add(rest.len() as u16 + 1));
+
+            let mut instrs = vec![Mnemonic::instr_b(
+                instr.opcode.inverse(),
+                instr.src,
+                skip.clone(),
+            )];
+            instrs.extend(rest);
+            (instrs, Some(skip))
+        }
+        Mnemonic::J(instr) => (jump(instr.opcode, instr.label), None),
+        m => (vec![m], None),
+    }
+}
+
+/// Lays out `items` once, with the branches and jumps in `reaches` relaxed.
+fn layout_once(
+    items: &[Spanned<Item>],
+    options: AssembleOptions,
+    reaches: &HashMap<(usize, usize), Reach>,
+) -> Layout {
     let mut mnemonics = Vec::new();
-    let mut table = HashMap::new();
+    let mut expansions = Vec::new();
+    let mut origins = Vec::new();
+    // The values of `LI` that are only known once every label is placed.
+    let mut deferred: Vec<Spanned<Expr>> = Vec::new();
+    let mut data = Vec::new();
+    let mut table = LabelTable::new();
+    let mut data_labels = HashSet::new();
+    let mut symbols = SymbolTable::new();
+    let mut definitions: HashMap<String, Span> = HashMap::new();
+    let mut symbol_definitions: HashMap<String, Span> = HashMap::new();
+    let mut errors = Vec::new();
+
+    let mut section = Section::Text;
+    let mut text_cursor = Cursor::new(Section::Text);
+    let mut data_cursor = Cursor::new(Section::Data);
+    for (index, line) in items.iter().enumerate() {
+        let line = line.clone();
+        let cursor = match section {
+            Section::Text => &mut text_cursor,
+            Section::Data => &mut data_cursor,
+        };
 
-    let mut current_index = 0;
-    for line in input {
-        match line {
+        match line.node {
             Item::Label(label) => {
-                table.insert(label, current_index);
+                if let Some(previous) = definitions.get(&label) {
+                    errors.push(Error::DuplicateLabel {
+                        label,
+                        span: line.span,
+                        previous: *previous,
+                    });
+                    continue;
+                }
+                if let Some(previous) = symbol_definitions.get(&label) {
+                    errors.push(Error::SymbolConflict {
+                        symbol: label,
+                        span: line.span,
+                        previous: *previous,
+                    });
+                    continue;
+                }
+
+                definitions.insert(label.clone(), line.span);
+                if section == Section::Data {
+                    data_labels.insert(label.clone());
+                }
+                table.insert(label, cursor.label());
             }
-            Item::Mnemonic(m) => {
-                current_index += 1;
-                mnemonics.push(m);
+            Item::Mnemonic(_) | Item::Pseudo(_) => {
+                if section != Section::Text {
+                    errors.push(Error::WrongSection {
+                        section: Section::Text,
+                        span: line.span,
+                    });
+                    continue;
+                }
+
+                let (source, instrs) = match line.node {
+                    Item::Mnemonic(mut m) => {
+                        // Use the value of a redefined symbol at this point.
+                        if let Mnemonic::I(instr) = &mut m {
+                            instr
+                                .immediate
+                                .substitute(&|name| symbols.get(name).copied());
+                        }
+
+                        (Item::Mnemonic(m.clone()), vec![m])
+                    }
+                    Item::Pseudo(mut pseudo) => {
+                        // The value decides how many instructions `LI` takes,
+                        // so it is evaluated here when possible.
+                        if let Pseudo::LI(_, value) = &mut pseudo {
+                            value.substitute(&|name| symbols.get(name).copied());
+                            match value {
+                                Expr::Num(value) => {
+                                    if let Err(err) = check_range(*value, LI_RANGE, line.span) {
+                                        errors.push(err);
+                                        continue;
+                                    }
+                                }
+                                value => deferred.push(Spanned::new(value.clone(), line.span)),
+                            }
+                        }
+
+                        let instrs = pseudo.expand(options.stack_pointer);
+                        (Item::Pseudo(pseudo), instrs)
+                    }
+                    _ => unreachable!(),
+                };
+
+                let mut words = Vec::new();
+                for (position, m) in instrs.into_iter().enumerate() {
+                    let origin = (index, position);
+                    let (relaxed, skip) = match (reaches.get(&origin), options.relax) {
+                        (Some(&reach), Some(scratch)) => relax(m, reach, scratch, cursor.label()),
+                        _ => (vec![m], None),
+                    };
+
+                    for m in relaxed {
+                        let addr = cursor.place(line.span, &mut errors);
+                        words.push((addr, m, origin));
+                    }
+                    if let Some(skip) = skip {
+                        table.insert(skip, cursor.label());
+                    }
+                }
+
+                // The listing shows what a sequence of instructions was written as.
+                let expanded = words.len() > 1 || matches!(source, Item::Pseudo(_));
+                for (position, (addr, m, origin)) in words.into_iter().enumerate() {
+                    mnemonics.push((addr, Spanned::new(m, line.span)));
+                    origins.push(origin);
+                    expansions.push(if expanded {
+                        Some(Expansion {
+                            source: source.clone(),
+                            index: position,
+                        })
+                    } else {
+                        None
+                    });
+                }
+            }
+            Item::Directive(Directive::Equ(name, value)) => {
+                if let Some(previous) = definitions.get(&name) {
+                    errors.push(Error::SymbolConflict {
+                        symbol: name,
+                        span: line.span,
+                        previous: *previous,
+                    });
+                    continue;
+                }
+
+                match value.eval(&|name| resolve(&symbols, &table, name), line.span) {
+                    Ok(value) => {
+                        symbol_definitions.entry(name.clone()).or_insert(line.span);
+                        symbols.insert(name, value);
+                    }
+                    Err(err) => errors.push(err),
+                }
+            }
+            Item::Directive(Directive::Section(next)) => section = next,
+            // The included items follow the directive.
+            Item::Directive(Directive::Include(_)) => {}
+            Item::Directive(Directive::Org(addr)) => {
+                // The address moves the following labels, so it must be known here.
+                let span = line.span;
+                let addr = addr
+                    .eval(&|name| resolve(&symbols, &table, name), span)
+                    .and_then(|addr| check_range(addr, 0..=i64::from(u16::MAX), span));
+
+                match addr {
+                    Ok(addr) => cursor.move_to(addr as u32),
+                    Err(err) => errors.push(err),
+                }
+            }
+            Item::Directive(Directive::Align(alignment)) => {
+                let span = line.span;
+                let current = i64::from(cursor.addr);
+                let addr = alignment
+                    .eval(&|name| resolve(&symbols, &table, name), span)
+                    .and_then(|alignment| check_range(alignment, 1..=i64::from(MEMORY_SIZE), span))
+                    .and_then(|alignment| {
+                        // Aligning to the end of the memory is fine as long
+                        // as nothing follows.
+                        let addr = (current + alignment - 1) / alignment * alignment;
+                        check_range(addr, 0..=i64::from(MEMORY_SIZE), span)
+                    });
+
+                match addr {
+                    Ok(addr) => cursor.move_to(addr as u32),
+                    Err(err) => errors.push(err),
+                }
+            }
+            Item::Directive(directive) => {
+                if section != Section::Data {
+                    errors.push(Error::WrongSection {
+                        section: Section::Data,
+                        span: line.span,
+                    });
+                    continue;
+                }
+
+                let words = match directive {
+                    Directive::Word(values) => values.into_iter().map(Datum::word).collect(),
+                    Directive::Byte(values) => values.into_iter().map(Datum::byte).collect(),
+                    Directive::Space(size) => {
+                        // The size moves the following labels, so it must be known here.
+                        let max = i64::from(MEMORY_SIZE - cursor.addr);
+                        let span = line.span;
+                        let size = size
+                            .eval(&|name| resolve(&symbols, &table, name), span)
+                            .and_then(|size| check_range(size, 0..=max, span));
+
+                        match size {
+                            Ok(size) => vec![Datum::word(Expr::Num(0)); size as usize],
+                            Err(err) => {
+                                errors.push(err);
+                                Vec::new()
+                            }
+                        }
+                    }
+                    Directive::Ascii(text) => text_data(&text),
+                    Directive::String(text) => {
+                        let mut words = text_data(&text);
+                        words.push(Datum::word(Expr::Num(0)));
+                        words
+                    }
+                    Directive::Equ(..)
+                    | Directive::Section(_)
+                    | Directive::Org(_)
+                    | Directive::Align(_)
+                    | Directive::Include(_) => unreachable!(),
+                };
+
+                for mut datum in words {
+                    datum.value.substitute(&|name| symbols.get(name).copied());
+
+                    let addr = cursor.place(line.span, &mut errors);
+                    data.push((addr, Spanned::new(datum, line.span)));
+                }
             }
         }
     }
 
-    return (mnemonics, table);
+    // Labels, and symbols used before they are defined, take their final value.
+    let lookup = |name: &str| resolve(&symbols, &table, name);
+    for (_, m) in &mut mnemonics {
+        if let Mnemonic::I(instr) = &mut m.node {
+            instr.immediate.substitute(&lookup);
+        }
+    }
+    for (_, datum) in &mut data {
+        datum.node.value.substitute(&lookup);
+    }
+    for value in deferred {
+        if let Err(err) = value
+            .node
+            .eval(&lookup, value.span)
+            .and_then(|v| check_range(v, LI_RANGE, value.span))
+        {
+            errors.push(err);
+        }
+    }
+
+    Layout {
+        mnemonics,
+        expansions,
+        origins,
+        data,
+        table,
+        data_labels,
+        symbols,
+        errors,
+    }
+}
+
+/// The values `LI` loads: any 16-bit value, signed or unsigned.
+const LI_RANGE: RangeInclusive<i64> = (i16::MIN as i64)..=(u16::MAX as i64);
+
+/// Returns one word per character of `text`.
+fn text_data(text: &str) -> Vec<Datum> {
+    text.chars()
+        .map(|c| Datum::word(Expr::Num(i64::from(u32::from(c)))))
+        .collect()
+}
+
+fn check_range(value: i64, range: RangeInclusive<i64>, span: Span) -> Result<i64> {
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(Error::ValueOutOfRange {
+            value,
+            min: *range.start(),
+            max: *range.end(),
+            span,
+        })
+    }
+}
+
+/// Looks `name` up as a symbol, then as a label.
+fn resolve(symbols: &SymbolTable, table: &LabelTable, name: &str) -> Option<i64> {
+    symbols
+        .get(name)
+        .copied()
+        .or_else(|| table.get(name).map(|&addr| addr.into()))
 }
 
-pub fn assemble<I>(input: I, table: &HashMap<String, i16>) -> Vec<Code>
+/// Returns a warning for every label that no branch, jump or expression
+/// refers to.
+///
+/// Labels starting with `_`, or `._` for local labels, are assumed to be
+/// unused on purpose.
+pub fn unused_labels(items: &[Spanned<Item>]) -> Vec<Warning> {
+    let mut scoped = items.to_vec();
+    scope_labels(&mut scoped);
+
+    let referenced: HashSet<&str> = scoped
+        .iter()
+        .flat_map(|item| match &item.node {
+            Item::Mnemonic(Mnemonic::B(instr)) => vec![instr.label.as_str()],
+            Item::Mnemonic(Mnemonic::J(instr)) => vec![instr.label.as_str()],
+            Item::Mnemonic(Mnemonic::I(instr)) => instr.immediate.symbols(),
+            Item::Pseudo(pseudo) => pseudo.symbols(),
+            Item::Directive(Directive::Equ(_, value)) => value.symbols(),
+            Item::Directive(Directive::Word(values)) | Item::Directive(Directive::Byte(values)) => {
+                values.iter().flat_map(Expr::symbols).collect()
+            }
+            Item::Directive(Directive::Space(size)) => size.symbols(),
+            Item::Directive(Directive::Org(value)) | Item::Directive(Directive::Align(value)) => {
+                value.symbols()
+            }
+            _ => Vec::new(),
+        })
+        .collect();
+
+    // Anonymous labels are not reported, since they have no name to show.
+    // A label defined again is reported as a duplicate, and only its first
+    // definition as unused.
+    let mut defined = HashSet::new();
+    items
+        .iter()
+        .zip(&scoped)
+        .filter_map(|(item, scoped)| match (&item.node, &scoped.node) {
+            (Item::Label(label), Item::Label(name))
+                if defined.insert(name.as_str())
+                    && !label.trim_start_matches('.').starts_with('_')
+                    && !is_anonymous(label)
+                    && !referenced.contains(name.as_str()) =>
+            {
+                Some(Warning::UnusedLabel {
+                    label: name.clone(),
+                    span: item.span,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Encodes each mnemonic at the address `convert` placed it at.
+pub fn assemble<I, M>(input: I, table: &LabelTable) -> Result<Vec<Code>>
 where
-    I: IntoIterator<Item = Mnemonic>,
+    I: IntoIterator<Item = (u16, M)>,
+    M: Into<Spanned<Mnemonic>>,
 {
     let mut result = Vec::new();
-    for (addr, line) in input.into_iter().enumerate() {
-        let code = encode(line, table, addr as i16);
-        result.push(code);
+    let mut errors = Vec::new();
+    for (addr, line) in input {
+        match encode(line, table, addr) {
+            Ok(code) => result.push(code),
+            Err(err) => errors.push(err),
+        }
     }
 
-    return result;
+    if errors.is_empty() {
+        Ok(result)
+    } else {
+        Err(Error::from_errors(errors))
+    }
+}
+
+/// Evaluates a word of the data section.
+fn encode_data(datum: &Spanned<Datum>) -> Result<Data> {
+    // `layout` substitutes the symbols, so any left are undefined.
+    let value = datum.node.value.eval(&|_| None, datum.span)?;
+    let value = check_range(value, datum.node.range.clone(), datum.span)?;
+
+    Ok(Data::new(value as u16, datum.span))
 }
 
-pub fn encode(line: Mnemonic, table: &HashMap<String, i16>, current_addr: i16) -> Code {
-    let code = match &line {
+pub fn encode<M>(line: M, table: &LabelTable, current_addr: u16) -> Result<Code>
+where
+    M: Into<Spanned<Mnemonic>>,
+{
+    let line = line.into();
+    let code = match &line.node {
         Mnemonic::R(instr) => {
             let f = instr.funct.id();
             let d = instr.dst.id();
@@ -48,7 +868,20 @@ pub fn encode(line: Mnemonic, table: &HashMap<String, i16>, current_addr: i16) -
         Mnemonic::I(instr) => {
             let c = instr.opcode.id();
             let d = instr.dst.id();
-            let x = instr.immediate as u16 & 0b00000_000_11111111;
+
+            // `convert` substitutes the symbols, so any left are undefined.
+            let immediate = instr.immediate.eval(&|_| None, line.span)?;
+
+            let range = instr.opcode.range();
+            if !range.contains(&immediate) {
+                return Err(Error::ImmediateOutOfRange {
+                    value: immediate,
+                    min: *range.start(),
+                    max: *range.end(),
+                    span: line.span,
+                });
+            }
+            let x = immediate as u16 & 0b00000_000_11111111;
 
             (c << 11) | (d << 8) | x
         }
@@ -56,16 +889,16 @@ pub fn encode(line: Mnemonic, table: &HashMap<String, i16>, current_addr: i16) -
             let c = instr.opcode.id();
             let s = instr.src.id();
 
-            let dst_addr = table[&instr.label];
-            let addr = dst_addr - current_addr;
+            let dst_addr = lookup(table, &instr.label, line.span)?;
+            let addr = displacement(&instr.label, dst_addr, current_addr, 8, line.span)?;
 
             (c << 11) | (s << 8) | addr as u16 & (0b00000_000_11111111)
         }
         Mnemonic::J(instr) => {
             let c = instr.opcode.id();
 
-            let dst_addr = table[&instr.label];
-            let addr = dst_addr - current_addr;
+            let dst_addr = lookup(table, &instr.label, line.span)?;
+            let addr = displacement(&instr.label, dst_addr, current_addr, 11, line.span)?;
 
             (c << 11) | addr as u16 & 0b00000_11111111111
         }
@@ -76,7 +909,44 @@ pub fn encode(line: Mnemonic, table: &HashMap<String, i16>, current_addr: i16) -
         }
     };
 
-    Code::new(code, line)
+    Ok(Code::new(code, line))
+}
+
+fn lookup(table: &LabelTable, label: &str, span: Span) -> Result<u16> {
+    table
+        .get(label)
+        .copied()
+        .ok_or_else(|| Error::UndefinedLabel {
+            label: label.to_string(),
+            span,
+        })
+}
+
+/// Returns the distance from `current_addr` to `dst_addr`, checking that it
+/// fits in a signed displacement field of `bits` bits. The program counter
+/// wraps around, so the distance is the shorter way around the memory.
+fn displacement(
+    label: &str,
+    dst_addr: u16,
+    current_addr: u16,
+    bits: u32,
+    span: Span,
+) -> Result<i16> {
+    let distance = i64::from(dst_addr.wrapping_sub(current_addr) as i16);
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+
+    if (min..=max).contains(&distance) {
+        Ok(distance as i16)
+    } else {
+        Err(Error::BranchOutOfRange {
+            label: label.to_string(),
+            distance,
+            min,
+            max,
+            span,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -84,12 +954,23 @@ pub mod tests {
     use std::collections::HashMap;
 
     use crate::{
-        assembler::{convert, encode},
+        assembler::{
+            assemble, assemble_items, assemble_items_with, convert, convert_with, encode,
+            unused_labels, AssembleOptions,
+        },
         code::*,
+        error::{Error, Warning},
+        expr::*,
         mnemonic::*,
         register::*,
+        span::{Location, Span, Spanned},
     };
 
+    /// Returns a span covering the whole of `line`.
+    fn span(line: u32) -> Span {
+        Span::new(Location::new(0, line, 1), Location::new(0, line, 2))
+    }
+
     #[test]
     fn generate_table() {
         {
@@ -105,11 +986,479 @@ pub mod tests {
                 Item::instr_r(FunctR::ADD, Register::R0, Register::R1), // 5
             ];
 
-            let (_, table) = convert(items);
-            assert_eq!(table["init".into()], 0);
-            assert_eq!(table["loop".into()], 3);
-            assert_eq!(table["end".into()], 5);
+            let (_, table, _) = convert(items).unwrap();
+            assert_eq!(table["init"], 0);
+            assert_eq!(table["loop"], 3);
+            assert_eq!(table["end"], 5);
+        }
+    }
+
+    #[test]
+    fn duplicate_label() {
+        let items = vec![
+            Spanned::new(Item::label("l0".into()), span(1)),
+            Spanned::new(
+                Item::instr_r(FunctR::NOP, Register::R0, Register::R0),
+                span(2),
+            ),
+            Spanned::new(Item::label("l0".into()), span(3)),
+        ];
+
+        match convert(items) {
+            Err(Error::DuplicateLabel {
+                label,
+                span: redefined,
+                previous,
+            }) => {
+                assert_eq!(label, "l0");
+                assert_eq!(redefined, span(3));
+                assert_eq!(previous, span(1));
+            }
+            result => panic!("expected a duplicate label error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn assemble_items_errors() {
+        let items = vec![
+            Spanned::new(Item::label("l0".into()), span(1)),
+            Spanned::new(Item::instr_j(OpcodeJ::JMP, "l1".into()), span(2)),
+            Spanned::new(Item::label("l0".into()), span(3)),
+        ];
+
+        let errors = assemble_items(items).unwrap_err().into_errors();
+        assert!(matches!(errors[0], Error::UndefinedLabel { .. }));
+        assert!(matches!(errors[1], Error::DuplicateLabel { .. }));
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn symbol_table() {
+        let symbol = |name: &str| Expr::Symbol(name.to_string());
+        let items = vec![
+            Item::instr_i(OpcodeI::LDIU, Register::R0, symbol("IO")), // 0
+            Item::equ("IO".into(), Expr::Num(2)),
+            Item::equ("COUNT".into(), Expr::Num(1)),
+            Item::equ("STEP".into(), symbol("COUNT")),
+            Item::instr_i(OpcodeI::ADDI, Register::R1, symbol("COUNT")), // 1
+            Item::equ("COUNT".into(), Expr::Num(3)),
+            Item::instr_i(OpcodeI::ADDI, Register::R1, symbol("COUNT")), // 2
+        ];
+
+        let (mnemonics, _, symbols) = convert(items).unwrap();
+        assert_eq!(symbols["IO"], 2);
+        assert_eq!(symbols["STEP"], 1);
+        assert_eq!(symbols["COUNT"], 3);
+
+        let immediates: Vec<Expr> = mnemonics
+            .into_iter()
+            .map(|(_, m)| match m.node {
+                Mnemonic::I(instr) => instr.immediate,
+                m => panic!("expected an I instruction, got {:?}", m),
+            })
+            .collect();
+        assert_eq!(immediates, vec![Expr::Num(2), Expr::Num(1), Expr::Num(3)]);
+    }
+
+    #[test]
+    fn label_expression() {
+        let symbol = |name: &str| Expr::Symbol(name.to_string());
+        let offset = |name: &str| Expr::binary(BinaryOp::Add, symbol(name), Expr::Num(4));
+        let items = vec![
+            Item::equ("BASE".into(), Expr::Num(0x1234)),
+            Item::instr_i(
+                OpcodeI::LDIU,
+                Register::R1,
+                Expr::unary(UnaryOp::Lo, offset("buffer")),
+            ), // 0
+            Item::instr_i(
+                OpcodeI::LDHI,
+                Register::R1,
+                Expr::unary(UnaryOp::Hi, offset("BASE")),
+            ), // 1
+            Item::label("buffer".into()),
+            Item::instr_r(FunctR::NOP, Register::R0, Register::R0), // 2
+            Item::equ("END".into(), offset("buffer")),
+        ];
+
+        let (mnemonics, _, symbols) = convert(items).unwrap();
+        assert_eq!(symbols["END"], 6);
+
+        let immediates: Vec<Expr> = mnemonics
+            .into_iter()
+            .filter_map(|(_, m)| match m.node {
+                Mnemonic::I(instr) => Some(instr.immediate),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(immediates, vec![Expr::Num(6), Expr::Num(0x12)]);
+    }
+
+    #[test]
+    fn symbol_conflict() {
+        let items = vec![
+            Spanned::new(Item::label("io".into()), span(1)),
+            Spanned::new(Item::equ("io".into(), Expr::Num(2)), span(2)),
+            Spanned::new(Item::equ("size".into(), Expr::Num(2)), span(3)),
+            Spanned::new(Item::label("size".into()), span(4)),
+        ];
+
+        let errors = convert(items).unwrap_err().into_errors();
+        let conflicts: Vec<(Span, Span)> = errors
+            .into_iter()
+            .map(|err| match err {
+                Error::SymbolConflict { span, previous, .. } => (span, previous),
+                err => panic!("expected a symbol conflict error, got {:?}", err),
+            })
+            .collect();
+        assert_eq!(conflicts, vec![(span(2), span(1)), (span(4), span(3))]);
+    }
+
+    #[test]
+    fn data_section() {
+        let items = vec![
+            Item::Directive(Directive::Section(Section::Data)),
+            Item::label("table".into()),
+            Item::Directive(Directive::Word(vec![
+                Expr::Num(-1),
+                Expr::Symbol("end".into()),
+            ])), // 0, 1
+            Item::Directive(Directive::Byte(vec![Expr::Num(0xff)])), // 2
+            Item::Directive(Directive::Space(Expr::Num(2))),         // 3, 4
+            Item::Directive(Directive::String("hi".into())),         // 5, 6, 7
+            Item::label("end".into()),
+            Item::Directive(Directive::Section(Section::Text)),
+            Item::label("start".into()),
+            Item::instr_i(
+                OpcodeI::LDIU,
+                Register::R0,
+                Expr::unary(UnaryOp::Lo, Expr::Symbol("table".into())),
+            ), // 0
+            Item::instr_j(OpcodeJ::JMP, "start".into()), // 1
+        ];
+
+        let program = assemble_items(items).unwrap();
+        let data: Vec<u16> = program.data.iter().map(Data::get_value).collect();
+        assert_eq!(data, vec![0xffff, 8, 0xff, 0, 0, 'h' as u16, 'i' as u16, 0]);
+
+        let codes: Vec<u16> = program.codes.iter().map(Code::get_code).collect();
+        assert_eq!(codes, vec![0b01001_000_00000000, 0b10100_11111111111]);
+
+        assert_eq!(
+            program.labels,
+            vec![("start".to_string(), 0)].into_iter().collect()
+        );
+        assert_eq!(
+            program.data_labels,
+            vec![("table".to_string(), 0), ("end".to_string(), 8)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn data_errors() {
+        let items = vec![
+            Spanned::new(
+                Item::Directive(Directive::Word(vec![Expr::Num(1)])),
+                span(1),
+            ),
+            Spanned::new(Item::Directive(Directive::Section(Section::Data)), span(2)),
+            Spanned::new(Item::instr_j(OpcodeJ::JMP, "end".into()), span(3)),
+            Spanned::new(
+                Item::Directive(Directive::Byte(vec![Expr::Num(256)])),
+                span(4),
+            ),
+            Spanned::new(
+                Item::Directive(Directive::Word(vec![Expr::Num(-32769)])),
+                span(5),
+            ),
+            Spanned::new(Item::Directive(Directive::Space(Expr::Num(-1))), span(6)),
+        ];
+
+        let errors = assemble_items(items).unwrap_err().into_errors();
+        assert!(matches!(
+            errors[0],
+            Error::WrongSection {
+                section: Section::Data,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[1],
+            Error::WrongSection {
+                section: Section::Text,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[2],
+            Error::ValueOutOfRange { value: 256, .. }
+        ));
+        assert!(matches!(
+            errors[3],
+            Error::ValueOutOfRange { value: -32769, .. }
+        ));
+        assert!(matches!(
+            errors[4],
+            Error::ValueOutOfRange { value: -1, .. }
+        ));
+        assert_eq!(errors.len(), 5);
+    }
+
+    #[test]
+    fn org_align() {
+        let items = vec![
+            Item::instr_j(OpcodeJ::JMP, "reset".into()), // 0
+            Item::Directive(Directive::Org(Expr::Num(4))),
+            Item::label("reset".into()),
+            Item::instr_r(FunctR::MV, Register::R0, Register::R1), // 4
+            Item::Directive(Directive::Align(Expr::Num(4))),
+            Item::label("handler".into()),
+            Item::instr_j(OpcodeJ::JMP, "reset".into()), // 8
+            Item::Directive(Directive::Section(Section::Data)),
+            Item::Directive(Directive::Org(Expr::Num(2))),
+            Item::label("table".into()),
+            Item::Directive(Directive::Word(vec![Expr::Num(7)])), // 2
+        ];
+
+        let (mnemonics, table, _) = convert(items.clone()).unwrap();
+        let addresses: Vec<u16> = mnemonics.iter().map(|(addr, _)| *addr).collect();
+        assert_eq!(addresses, vec![0, 4, 8]);
+        assert_eq!(table["reset"], 4);
+        assert_eq!(table["handler"], 8);
+        assert_eq!(table["table"], 2);
+
+        let program = assemble_items_with(
+            items,
+            AssembleOptions {
+                pad: 0xffff,
+                ..AssembleOptions::default()
+            },
+        )
+        .unwrap();
+        let codes: Vec<u16> = program.codes.iter().map(Code::get_code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                0b10100_00000000100,
+                0xffff,
+                0xffff,
+                0xffff,
+                0b00000_000_001_00001,
+                0xffff,
+                0xffff,
+                0xffff,
+                0b10100_11111111100,
+            ]
+        );
+        assert_eq!(program.codes[1].get_mnemonic(), None);
+
+        let data: Vec<u16> = program.data.iter().map(Data::get_value).collect();
+        assert_eq!(data, vec![0, 0, 7]);
+
+        // Aligning an aligned address does not move it.
+        let items = vec![
+            Item::Directive(Directive::Align(Expr::Num(2))),
+            Item::instr_r(FunctR::NOP, Register::R0, Register::R0),
+        ];
+        assert_eq!(assemble_items(items).unwrap().codes.len(), 1);
+    }
+
+    #[test]
+    fn org_top_of_memory() {
+        // Vectors at the top of the memory reach the start by wrapping around.
+        let items = vec![
+            Item::label("reset".into()),
+            Item::instr_j(OpcodeJ::JMP, "reset".into()), // 0
+            Item::Directive(Directive::Org(Expr::Num(0xfffe))),
+            Item::label("vectors".into()),
+            Item::instr_j(OpcodeJ::JMP, "reset".into()), // 0xfffe
+            Item::instr_j(OpcodeJ::JMP, "end".into()),   // 0xffff
+            Item::label("end".into()),
+        ];
+        let program = assemble_items(items).unwrap();
+        assert_eq!(program.labels["vectors"], 0xfffe);
+        assert_eq!(program.labels["end"], 0);
+        assert_eq!(program.codes.len(), 0x10000);
+        assert_eq!(program.codes[0xfffe].get_code(), 0b10100_00000000010);
+        assert_eq!(program.codes[0xffff].get_code(), 0b10100_00000000001);
+
+        let nop = || Item::instr_r(FunctR::NOP, Register::R0, Register::R0);
+        let items = vec![
+            Spanned::new(Item::Directive(Directive::Org(Expr::Num(0xffff))), span(1)),
+            Spanned::new(nop(), span(2)),
+            Spanned::new(nop(), span(3)),
+            Spanned::new(nop(), span(4)),
+            Spanned::new(Item::Directive(Directive::Org(Expr::Num(0x10000))), span(5)),
+            Spanned::new(Item::Directive(Directive::Section(Section::Data)), span(6)),
+            Spanned::new(
+                Item::Directive(Directive::Word(vec![Expr::Num(1)])),
+                span(7),
+            ),
+            Spanned::new(
+                Item::Directive(Directive::Align(Expr::Num(0x10000))),
+                span(8),
+            ),
+            Spanned::new(
+                Item::Directive(Directive::Word(vec![Expr::Num(2)])),
+                span(9),
+            ),
+        ];
+        let errors = assemble_items(items).unwrap_err().into_errors();
+        assert!(matches!(
+            errors[0],
+            Error::AddressOverflow {
+                section: Section::Text,
+                span: s,
+            } if s == span(3)
+        ));
+        assert!(matches!(
+            errors[1],
+            Error::ValueOutOfRange {
+                value: 0x10000,
+                max: 0xffff,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[2],
+            Error::AddressOverflow {
+                section: Section::Data,
+                span: s,
+            } if s == span(9)
+        ));
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn overlap() {
+        let nop = || Item::instr_r(FunctR::NOP, Register::R0, Register::R0);
+        let items = vec![
+            Spanned::new(nop(), span(1)), // 0
+            Spanned::new(nop(), span(2)), // 1
+            Spanned::new(Item::Directive(Directive::Org(Expr::Num(0))), span(3)),
+            Spanned::new(nop(), span(4)), // 0
+            Spanned::new(nop(), span(5)), // 1
+            Spanned::new(nop(), span(6)), // 2
+            Spanned::new(Item::Directive(Directive::Org(Expr::Num(-1))), span(7)),
+            Spanned::new(Item::Directive(Directive::Align(Expr::Num(0))), span(8)),
+        ];
+
+        let errors = assemble_items(items).unwrap_err().into_errors();
+        match &errors[0] {
+            Error::Overlap {
+                address,
+                span: overlapping,
+                previous,
+            } => {
+                assert_eq!(*address, 0);
+                assert_eq!(*overlapping, span(4));
+                assert_eq!(*previous, span(1));
+            }
+            err => panic!("expected an overlap error, got {:?}", err),
         }
+        assert!(matches!(
+            errors[1],
+            Error::ValueOutOfRange { value: -1, .. }
+        ));
+        assert!(matches!(errors[2], Error::ValueOutOfRange { value: 0, .. }));
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn symbol_errors() {
+        let items = vec![
+            Spanned::new(Item::equ("BIG".into(), Expr::Num(300)), span(1)),
+            Spanned::new(
+                Item::instr_i(OpcodeI::LDI, Register::R0, Expr::Symbol("BIG".into())),
+                span(2),
+            ),
+            Spanned::new(
+                Item::instr_i(OpcodeI::LDI, Register::R0, Expr::Symbol("NONE".into())),
+                span(3),
+            ),
+            Spanned::new(
+                Item::equ("COPY".into(), Expr::Symbol("LATER".into())),
+                span(4),
+            ),
+            Spanned::new(Item::equ("LATER".into(), Expr::Num(0)), span(5)),
+        ];
+
+        let errors = assemble_items(items).unwrap_err().into_errors();
+        assert!(matches!(
+            errors[0],
+            Error::ImmediateOutOfRange { value: 300, .. }
+        ));
+        assert!(matches!(errors[1], Error::UndefinedSymbol { .. }));
+        assert!(matches!(errors[2], Error::UndefinedSymbol { .. }));
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn unused_label() {
+        let items = vec![
+            Spanned::new(Item::label("start".into()), span(1)),
+            Spanned::new(Item::label("_entry".into()), span(2)),
+            Spanned::new(Item::label("loop".into()), span(3)),
+            Spanned::new(
+                Item::instr_b(OpcodeB::BNZ, Register::R0, "loop".into()),
+                span(4),
+            ),
+            Spanned::new(Item::instr_j(OpcodeJ::JMP, "end".into()), span(5)),
+            Spanned::new(Item::label("end".into()), span(6)),
+            Spanned::new(Item::label("data".into()), span(7)),
+            Spanned::new(
+                Item::instr_i(
+                    OpcodeI::LDIU,
+                    Register::R0,
+                    Expr::unary(UnaryOp::Lo, Expr::Symbol("data".into())),
+                ),
+                span(8),
+            ),
+        ];
+
+        assert_eq!(
+            unused_labels(&items),
+            vec![Warning::UnusedLabel {
+                label: "start".into(),
+                span: span(1),
+            }]
+        );
+
+        // A duplicate label is warned about once, at its first definition.
+        let items = vec![
+            Spanned::new(Item::label("twice".into()), span(1)),
+            Spanned::new(Item::label("twice".into()), span(2)),
+        ];
+        assert_eq!(
+            unused_labels(&items),
+            vec![Warning::UnusedLabel {
+                label: "twice".into(),
+                span: span(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let items = vec![
+            Item::instr_b(OpcodeB::BEZ, Register::R0, "l0".into()),
+            Item::instr_r(FunctR::NOP, Register::R0, Register::R0),
+            Item::instr_j(OpcodeJ::JMP, "l1".into()),
+        ];
+
+        let (mnemonics, table, _) = convert(items).unwrap();
+        let errors = assemble(mnemonics, &table).unwrap_err().into_errors();
+
+        let labels: Vec<&str> = errors
+            .iter()
+            .map(|err| match err {
+                Error::UndefinedLabel { label, .. } => label.as_str(),
+                err => panic!("expected an undefined label error, got {:?}", err),
+            })
+            .collect();
+        assert_eq!(labels, vec!["l0", "l1"]);
     }
 
     #[test]
@@ -120,70 +1469,70 @@ pub mod tests {
             // NOP
             let m = Mnemonic::instr_r(FunctR::NOP, Register::R0, Register::R0);
             let c = Code::new(0b00000_000_000_00000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // MV r0, r1
             let m = Mnemonic::instr_r(FunctR::MV, Register::R0, Register::R1);
             let c = Code::new(0b00000_000_001_00001, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // AND r0, r1
             let m = Mnemonic::instr_r(FunctR::AND, Register::R0, Register::R1);
             let c = Code::new(0b00000_000_001_00010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // OR r0, r1
             let m = Mnemonic::instr_r(FunctR::OR, Register::R0, Register::R1);
             let c = Code::new(0b00000_000_001_00011, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // SL r0
             let m = Mnemonic::instr_r(FunctR::SL, Register::R1, Register::R0);
             let c = Code::new(0b00000_001_000_00100, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // SR r0
             let m = Mnemonic::instr_r(FunctR::SR, Register::R1, Register::R0);
             let c = Code::new(0b00000_001_000_00101, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // ADD r0, r1
             let m = Mnemonic::instr_r(FunctR::ADD, Register::R0, Register::R1);
             let c = Code::new(0b00000_000_001_00110, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // SUB r0, r1
             let m = Mnemonic::instr_r(FunctR::SUB, Register::R0, Register::R1);
             let c = Code::new(0b00000_000_001_00111, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // ST r1, (r2)
             let m = Mnemonic::instr_r(FunctR::ST, Register::R1, Register::R2);
             let c = Code::new(0b00000_001_010_01000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LD r1, (r2)
             let m = Mnemonic::instr_r(FunctR::LD, Register::R1, Register::R2);
             let c = Code::new(0b00000_001_010_01001, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
     }
 
@@ -195,35 +1544,35 @@ pub mod tests {
             // LDI r1, #2
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, 2);
             let c = Code::new(0b01000_001_00000010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDIU r1, #2
             let m = Mnemonic::instr_i(OpcodeI::LDIU, Register::R1, 2);
             let c = Code::new(0b01001_001_00000010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // ADDI r1, #2
             let m = Mnemonic::instr_i(OpcodeI::ADDI, Register::R1, 2);
             let c = Code::new(0b01100_001_00000010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // ADDIU r1, #2
             let m = Mnemonic::instr_i(OpcodeI::ADDIU, Register::R1, 2);
             let c = Code::new(0b01101_001_00000010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDHI r1, #2
             let m = Mnemonic::instr_i(OpcodeI::LDHI, Register::R1, 2);
             let c = Code::new(0b01010_001_00000010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
     }
 
@@ -235,35 +1584,87 @@ pub mod tests {
             // LDI r1, #-127
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, -127);
             let c = Code::new(0b01000_001_10000001, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r1, #-1
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, -1);
             let c = Code::new(0b01000_001_11111111, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r1, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, 0);
             let c = Code::new(0b01000_001_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r1, #1
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, 1);
             let c = Code::new(0b01000_001_00000001, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r1, #127
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, 127);
             let c = Code::new(0b01000_001_01111111, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
+        }
+    }
+
+    #[test]
+    fn encode_i_range() {
+        let table = &HashMap::new();
+
+        {
+            // LDI r1, #-128
+            let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, -128);
+            let c = Code::new(0b01000_001_10000000, m.clone());
+            assert_eq!(c, encode(m, table, 0).unwrap());
+        }
+
+        {
+            // LDIU r1, #255
+            let m = Mnemonic::instr_i(OpcodeI::LDIU, Register::R1, 255);
+            let c = Code::new(0b01001_001_11111111, m.clone());
+            assert_eq!(c, encode(m, table, 0).unwrap());
+        }
+
+        {
+            // ADDIU r1, #200
+            let m = Mnemonic::instr_i(OpcodeI::ADDIU, Register::R1, 200);
+            let c = Code::new(0b01101_001_11001000, m.clone());
+            assert_eq!(c, encode(m, table, 0).unwrap());
+        }
+
+        {
+            // LDHI r1, #0x80
+            let m = Mnemonic::instr_i(OpcodeI::LDHI, Register::R1, 0x80);
+            let c = Code::new(0b01010_001_10000000, m.clone());
+            assert_eq!(c, encode(m, table, 0).unwrap());
+        }
+
+        let out_of_range = vec![
+            (OpcodeI::LDI, -129, -128, 127),
+            (OpcodeI::LDI, 128, -128, 127),
+            (OpcodeI::ADDI, 200, -128, 127),
+            (OpcodeI::LDIU, -1, 0, 255),
+            (OpcodeI::ADDIU, 256, 0, 255),
+            (OpcodeI::LDHI, -128, 0, 255),
+        ];
+
+        for (opcode, immediate, expected_min, expected_max) in out_of_range {
+            let m = Mnemonic::instr_i(opcode, Register::R1, immediate);
+            match encode(m, table, 0) {
+                Err(Error::ImmediateOutOfRange {
+                    value, min, max, ..
+                }) => assert_eq!((value, min, max), (immediate, expected_min, expected_max)),
+                result => panic!("expected an out of range error, got {:?}", result),
+            }
         }
     }
 
@@ -275,56 +1676,56 @@ pub mod tests {
             // LDI r0, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R0, 0);
             let c = Code::new(0b01000_000_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r1, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R1, 0);
             let c = Code::new(0b01000_001_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r2, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R2, 0);
             let c = Code::new(0b01000_010_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r3, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R3, 0);
             let c = Code::new(0b01000_011_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r4, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R4, 0);
             let c = Code::new(0b01000_100_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r5, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R5, 0);
             let c = Code::new(0b01000_101_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r6, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R6, 0);
             let c = Code::new(0b01000_110_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // LDI r7, #0
             let m = Mnemonic::instr_i(OpcodeI::LDI, Register::R7, 0);
             let c = Code::new(0b01000_111_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
     }
 
@@ -343,27 +1744,27 @@ pub mod tests {
             Item::instr_r(FunctR::NOP, Register::R0, Register::R0), // 6    NOP
         ];
 
-        let (_, table) = convert(items);
+        let (_, table, _) = convert(items).unwrap();
 
         {
             // 1    BEZ r0, l0  (1 -> 0 => -1)
             let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "l0".into());
             let c = Code::new(0b10000_000_11111111, m.clone());
-            assert_eq!(c, encode(m, &table, 1));
+            assert_eq!(c, encode(m, &table, 1).unwrap());
         }
 
         {
             // 3    BEZ r0, l1 // (3 -> 3 => 0)
             let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "l1".into());
             let c = Code::new(0b10000_000_00000000, m.clone());
-            assert_eq!(c, encode(m, &table, 3));
+            assert_eq!(c, encode(m, &table, 3).unwrap());
         }
 
         {
             // 5    BEZ r0, l2 // (5 -> 6 => 1)
             let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "l2".into());
             let c = Code::new(0b10000_000_00000001, m.clone());
-            assert_eq!(c, encode(m, &table, 5));
+            assert_eq!(c, encode(m, &table, 5).unwrap());
         }
     }
 
@@ -379,28 +1780,28 @@ pub mod tests {
             // BEZ r0, label
             let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "label".into());
             let c = Code::new(0b10000_000_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // BNZ r0, label
             let m = Mnemonic::instr_b(OpcodeB::BNZ, Register::R0, "label".into());
             let c = Code::new(0b10001_000_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // BPL r0, label
             let m = Mnemonic::instr_b(OpcodeB::BPL, Register::R0, "label".into());
             let c = Code::new(0b10010_000_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // BMI r0, label
             let m = Mnemonic::instr_b(OpcodeB::BMI, Register::R0, "label".into());
             let c = Code::new(0b10011_000_00000000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
     }
 
@@ -419,27 +1820,110 @@ pub mod tests {
             Item::instr_r(FunctR::NOP, Register::R0, Register::R0), // 6    NOP
         ];
 
-        let (_, table) = convert(items);
+        let (_, table, _) = convert(items).unwrap();
 
         {
             // 1    JMP l0 // (1 -> 0 => -1)
             let m = Mnemonic::instr_j(OpcodeJ::JMP, "l0".into());
             let c = Code::new(0b10100_11111111111, m.clone());
-            assert_eq!(c, encode(m, &table, 1));
+            assert_eq!(c, encode(m, &table, 1).unwrap());
         }
 
         {
             // 3    JMP l1 // (3 -> 3 => 0)
             let m = Mnemonic::instr_j(OpcodeJ::JMP, "l1".into());
             let c = Code::new(0b10100_00000000000, m.clone());
-            assert_eq!(c, encode(m, &table, 3));
+            assert_eq!(c, encode(m, &table, 3).unwrap());
         }
 
         {
             // 5    JMP l2 // (5 -> 6 => 1)
             let m = Mnemonic::instr_j(OpcodeJ::JMP, "l2".into());
             let c = Code::new(0b10100_00000000001, m.clone());
-            assert_eq!(c, encode(m, &table, 5));
+            assert_eq!(c, encode(m, &table, 5).unwrap());
+        }
+    }
+
+    #[test]
+    fn encode_b_range() {
+        let nop = || Item::instr_r(FunctR::NOP, Register::R0, Register::R0);
+
+        let mut items = vec![Item::label("back".into())];
+        items.extend((0..128).map(|_| nop()));
+        items.push(Item::label("near".into()));
+        items.push(nop());
+        items.push(Item::label("far".into()));
+
+        let (_, table, _) = convert(items).unwrap();
+
+        {
+            // 127  BEZ r0, back // (127 -> 0 => -127)
+            let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "back".into());
+            let c = Code::new(0b10000_000_10000001, m.clone());
+            assert_eq!(c, encode(m, &table, 127).unwrap());
+        }
+
+        {
+            // 128  BEZ r0, back // (128 -> 0 => -128)
+            let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "back".into());
+            let c = Code::new(0b10000_000_10000000, m.clone());
+            assert_eq!(c, encode(m, &table, 128).unwrap());
+        }
+
+        {
+            // 129  BEZ r0, back // (129 -> 0 => -129)
+            let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "back".into());
+            match encode(m, &table, 129) {
+                Err(Error::BranchOutOfRange {
+                    distance, min, max, ..
+                }) => assert_eq!((distance, min, max), (-129, -128, 127)),
+                result => panic!("expected an out of range error, got {:?}", result),
+            }
+        }
+
+        {
+            // 1    BEZ r0, near // (1 -> 128 => 127)
+            let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "near".into());
+            let c = Code::new(0b10000_000_01111111, m.clone());
+            assert_eq!(c, encode(m, &table, 1).unwrap());
+        }
+
+        {
+            // 1    BEZ r0, far // (1 -> 129 => 128)
+            let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "far".into());
+            match encode(m, &table, 1) {
+                Err(Error::BranchOutOfRange {
+                    distance, min, max, ..
+                }) => assert_eq!((distance, min, max), (128, -128, 127)),
+                result => panic!("expected an out of range error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn encode_j_range() {
+        let table = &{
+            let mut t = HashMap::new();
+            t.insert("label".into(), 0);
+            t
+        };
+
+        {
+            // 1024 JMP label // (1024 -> 0 => -1024)
+            let m = Mnemonic::instr_j(OpcodeJ::JMP, "label".into());
+            let c = Code::new(0b10100_10000000000, m.clone());
+            assert_eq!(c, encode(m, table, 1024).unwrap());
+        }
+
+        {
+            // 1025 JMP label // (1025 -> 0 => -1025)
+            let m = Mnemonic::instr_j(OpcodeJ::JMP, "label".into());
+            match encode(m, table, 1025) {
+                Err(Error::BranchOutOfRange {
+                    distance, min, max, ..
+                }) => assert_eq!((distance, min, max), (-1025, -1024, 1023)),
+                result => panic!("expected an out of range error, got {:?}", result),
+            }
         }
     }
 
@@ -458,27 +1942,50 @@ pub mod tests {
             Item::instr_r(FunctR::NOP, Register::R0, Register::R0), // 6    NOP
         ];
 
-        let (_, table) = convert(items);
+        let (_, table, _) = convert(items).unwrap();
 
         {
             // 1    JAL l0 // (1 -> 0 => -1)
             let m = Mnemonic::instr_j(OpcodeJ::JAL, "l0".into());
             let c = Code::new(0b10101_11111111111, m.clone());
-            assert_eq!(c, encode(m, &table, 1));
+            assert_eq!(c, encode(m, &table, 1).unwrap());
         }
 
         {
             // 3    JMP l1 // (3 -> 3 => 0)
             let m = Mnemonic::instr_j(OpcodeJ::JAL, "l1".into());
             let c = Code::new(0b10101_00000000000, m.clone());
-            assert_eq!(c, encode(m, &table, 3));
+            assert_eq!(c, encode(m, &table, 3).unwrap());
         }
 
         {
             // 5    JAL l2 // (5 -> 6 => 1)
             let m = Mnemonic::instr_j(OpcodeJ::JAL, "l2".into());
             let c = Code::new(0b10101_00000000001, m.clone());
-            assert_eq!(c, encode(m, &table, 5));
+            assert_eq!(c, encode(m, &table, 5).unwrap());
+        }
+    }
+
+    #[test]
+    fn encode_undefined_label() {
+        let table = &HashMap::new();
+
+        {
+            // BEZ r0, label
+            let m = Mnemonic::instr_b(OpcodeB::BEZ, Register::R0, "label".into());
+            match encode(m, table, 0) {
+                Err(Error::UndefinedLabel { label, .. }) => assert_eq!(label, "label"),
+                result => panic!("expected an undefined label error, got {:?}", result),
+            }
+        }
+
+        {
+            // JMP label
+            let m = Mnemonic::instr_j(OpcodeJ::JMP, "label".into());
+            match encode(m, table, 0) {
+                Err(Error::UndefinedLabel { label, .. }) => assert_eq!(label, "label"),
+                result => panic!("expected an undefined label error, got {:?}", result),
+            }
         }
     }
 
@@ -489,14 +1996,14 @@ pub mod tests {
             // JR r0
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R0);
             let c = Code::new(0b00000_000_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JALR r0
             let m = Mnemonic::instr_jr(FunctJR::JALR, Register::R0);
             let c = Code::new(0b00000_000_000_11000, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
     }
 
@@ -507,56 +2014,279 @@ pub mod tests {
             // JR r0
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R0);
             let c = Code::new(0b00000_000_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r1
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R1);
             let c = Code::new(0b00000_001_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r2
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R2);
             let c = Code::new(0b00000_010_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r3
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R3);
             let c = Code::new(0b00000_011_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r4
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R4);
             let c = Code::new(0b00000_100_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r5
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R5);
             let c = Code::new(0b00000_101_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r6
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R6);
             let c = Code::new(0b00000_110_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
 
         {
             // JR r7
             let m = Mnemonic::instr_jr(FunctJR::JR, Register::R7);
             let c = Code::new(0b00000_111_000_01010, m.clone());
-            assert_eq!(c, encode(m, table, 0));
+            assert_eq!(c, encode(m, table, 0).unwrap());
         }
     }
+
+    #[test]
+    fn pseudo() {
+        let li = |value: i64| {
+            let items = vec![Item::Pseudo(Pseudo::LI(Register::R0, Expr::Num(value)))];
+            let (mnemonics, _, _) = convert(items).unwrap();
+            mnemonics
+                .into_iter()
+                .map(|(_, m)| m.node.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(li(-128), vec!["LDI r0, #-128"]);
+        assert_eq!(li(255), vec!["LDIU r0, #255"]);
+        assert_eq!(li(0x1200), vec!["LDHI r0, #18"]);
+        assert_eq!(li(0x1234), vec!["LDHI r0, #18", "ADDIU r0, #52"]);
+        assert_eq!(li(-200), vec!["LDHI r0, #255", "ADDIU r0, #56"]);
+
+        let items = vec![
+            Item::Pseudo(Pseudo::LA(Register::R1, "end".into())), // 0, 1
+            Item::Pseudo(Pseudo::CALL("f".into())),               // 2
+            Item::label("f".into()),
+            Item::Pseudo(Pseudo::PUSH(Register::R1)), // 3, 4
+            Item::Pseudo(Pseudo::NOT(Register::R1, Register::R1)), // 5..=11
+            Item::Pseudo(Pseudo::POP(Register::R1)),  // 12, 13
+            Item::Pseudo(Pseudo::RET),                // 14
+            Item::label("end".into()),
+        ];
+        let options = AssembleOptions {
+            stack_pointer: Register::R5,
+            ..AssembleOptions::default()
+        };
+        let (mnemonics, table, _) = convert_with(items.clone(), options).unwrap();
+        assert_eq!(mnemonics.len(), 15);
+        assert_eq!(table["f"], 3);
+        assert_eq!(table["end"], 15);
+        assert_eq!(
+            mnemonics[3].1.node,
+            Mnemonic::instr_i(OpcodeI::ADDI, Register::R5, -1)
+        );
+        assert_eq!(
+            mnemonics[7].1.node,
+            Mnemonic::instr_r(FunctR::MV, Register::R0, Register::R1)
+        );
+        assert_eq!(
+            mnemonics[14].1.node,
+            Mnemonic::instr_jr(FunctJR::JR, Register::R7)
+        );
+
+        // The listing shows the pseudo-instruction next to its expansion.
+        let program = assemble_items_with(items, options).unwrap();
+        let lines: Vec<String> = program.codes[..2]
+            .iter()
+            .map(|code| code.get_line(true, true))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "01010_001_00000000   // LA r1, end => LDHI r1, #0",
+                "01101_001_00001111   //            => ADDIU r1, #15",
+            ]
+        );
+        assert_eq!(program.codes[2].get_expansion().unwrap().index, 0);
+    }
+
+    #[test]
+    fn pseudo_range() {
+        let items = vec![
+            Spanned::new(
+                Item::Pseudo(Pseudo::LI(Register::R0, Expr::Num(0x10000))),
+                span(1),
+            ),
+            Spanned::new(
+                Item::Pseudo(Pseudo::LI(Register::R0, Expr::Symbol("LATER".into()))),
+                span(2),
+            ),
+            Spanned::new(Item::equ("LATER".into(), Expr::Num(-0x8001)), span(3)),
+        ];
+
+        let errors = assemble_items(items).unwrap_err().into_errors();
+        assert!(matches!(
+            errors[0],
+            Error::ValueOutOfRange { value: 0x10000, .. }
+        ));
+        assert!(matches!(
+            errors[1],
+            Error::ValueOutOfRange { value: -0x8001, .. }
+        ));
+        assert_eq!(errors[1].span(), Some(span(2)));
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn relax() {
+        let nops =
+            |count: usize| vec![Item::instr_r(FunctR::NOP, Register::R0, Register::R0); count];
+
+        let mut items = vec![
+            Item::instr_b(OpcodeB::BEZ, Register::R0, "far".into()), // 0, 1
+            Item::instr_b(OpcodeB::BPL, Register::R1, "very_far".into()), // 2..=5
+            Item::instr_j(OpcodeJ::JAL, "very_far".into()),          // 6..=8
+            Item::instr_b(OpcodeB::BNZ, Register::R2, "near".into()), // 9
+            Item::label("near".into()),
+        ];
+        items.extend(nops(200));
+        items.push(Item::label("far".into()));
+        items.extend(nops(1100));
+        items.push(Item::label("very_far".into()));
+
+        let errors = assemble_items(items.clone()).unwrap_err().into_errors();
+        assert!(errors
+            .iter()
+            .all(|err| matches!(err, Error::BranchOutOfRange { .. })));
+        assert_eq!(errors.len(), 3);
+
+        let options = AssembleOptions {
+            relax: Some(Register::R5),
+            ..AssembleOptions::default()
+        };
+        let (mnemonics, table, _) = convert_with(items.clone(), options).unwrap();
+        assert_eq!(table["near"], 10);
+        assert_eq!(table["far"], 210);
+        assert_eq!(table["very_far"], 1310);
+
+        let instrs: Vec<String> = mnemonics[..10]
+            .iter()
+            .map(|(_, m)| m.node.to_string())
+            .collect();
+        assert_eq!(
+            instrs,
+            vec![
+                "BNZ r0, @2",
+                "JMP far",
+                "BMI r1, @6",
+                "LDHI r5, #5",
+                "ADDIU r5, #30",
+                "JR r5",
+                "LDHI r7, #5",
+                "ADDIU r7, #30",
+                "JALR r7",
+                "BNZ r2, near",
+            ]
+        );
+
+        let program = assemble_items_with(items, options).unwrap();
+        assert_eq!(
+            program.codes[0].get_line(true, true),
+            "10001_000_00000010   // BEZ r0, far => BNZ r0, @2"
+        );
+        assert_eq!(program.codes[9].get_expansion(), None);
+    }
+
+    #[test]
+    fn scoped_labels() {
+        let nop = || Item::instr_r(FunctR::NOP, Register::R0, Register::R0);
+        let items = vec![
+            Item::label("first".into()),
+            Item::label(".loop".into()),
+            nop(),                                                     // 0
+            Item::instr_b(OpcodeB::BNZ, Register::R0, ".loop".into()), // 1
+            Item::label("1".into()),
+            Item::instr_b(OpcodeB::BEZ, Register::R0, "1f".into()), // 2
+            Item::instr_b(OpcodeB::BEZ, Register::R0, "1b".into()), // 3
+            Item::label("1".into()),
+            Item::instr_j(OpcodeJ::JMP, "1b".into()), // 4
+            Item::label("second".into()),
+            nop(), // 5
+            Item::label(".loop".into()),
+            Item::instr_i(OpcodeI::LDI, Register::R1, Expr::Symbol(".loop".into())), // 6
+            Item::instr_j(OpcodeJ::JMP, "first".into()),                             // 7
+        ];
+
+        let (mnemonics, table, _) = convert(items.clone()).unwrap();
+        assert_eq!(table["first.loop"], 0);
+        assert_eq!(table["second.loop"], 6);
+        assert_eq!(table["1@0"], 2);
+        assert_eq!(table["1@1"], 4);
+        assert!(!table.contains_key(".loop"));
+
+        let labels: Vec<&str> = mnemonics
+            .iter()
+            .filter_map(|(_, m)| match &m.node {
+                Mnemonic::B(instr) => Some(instr.label.as_str()),
+                Mnemonic::J(instr) => Some(instr.label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, vec!["first.loop", "1@1", "1@0", "1@1", "first"]);
+        assert_eq!(
+            mnemonics[6].1.node,
+            Mnemonic::instr_i(OpcodeI::LDI, Register::R1, 6)
+        );
+
+        // A local label is only unused within its scope.
+        let warnings: Vec<String> = unused_labels(
+            &items
+                .into_iter()
+                .map(|item| Spanned::new(item, span(1)))
+                .collect::<Vec<_>>(),
+        )
+        .into_iter()
+        .map(|warning| match warning {
+            Warning::UnusedLabel { label, .. } => label,
+        })
+        .collect();
+        assert_eq!(warnings, vec!["second"]);
+
+        // References without a matching label keep their names.
+        let items = vec![
+            Item::instr_b(OpcodeB::BEZ, Register::R0, "1b".into()),
+            Item::label("1".into()),
+            Item::instr_b(OpcodeB::BEZ, Register::R0, "1f".into()),
+        ];
+        let labels: Vec<String> = assemble_items(items)
+            .unwrap_err()
+            .into_errors()
+            .into_iter()
+            .map(|err| match err {
+                Error::UndefinedLabel { label, .. } => label,
+                err => panic!("expected an undefined label error, got {:?}", err),
+            })
+            .collect();
+        assert_eq!(labels, vec!["1b", "1f"]);
+    }
 }
@@ -1,16 +1,69 @@
-use crate::mnemonic::*;
+use crate::{
+    assembler::LabelTable,
+    mnemonic::*,
+    span::{Span, Spanned},
+};
+
+/// An assembled program: the images of the instruction and data memories.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Program {
+    pub codes: Vec<Code>,
+    pub data: Vec<Data>,
+
+    /// The addresses of the labels of the instruction memory.
+    pub labels: LabelTable,
+
+    /// The addresses of the labels of the data memory.
+    pub data_labels: LabelTable,
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Code {
     code: u16,
-    instruction: Mnemonic,
+
+    /// The instruction this code was assembled from, or `None` for a pad
+    /// word filling a gap left by `.org` or `.align`.
+    instruction: Option<Spanned<Mnemonic>>,
+
+    /// The instruction as written, if it expanded to several.
+    expansion: Option<Expansion>,
+}
+
+/// Where an instruction stands in the expansion of a pseudo-instruction or
+/// of a branch lengthened to reach its target.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expansion {
+    /// The instruction as written.
+    pub source: Item,
+
+    /// The position of the instruction in the expansion, from 0.
+    pub index: usize,
 }
 
 impl Code {
-    pub fn new(code: u16, instruction: Mnemonic) -> Self {
+    pub fn new<M>(code: u16, instruction: M) -> Self
+    where
+        M: Into<Spanned<Mnemonic>>,
+    {
         Code {
-            code: code,
-            instruction: instruction,
+            code,
+            instruction: Some(instruction.into()),
+            expansion: None,
+        }
+    }
+
+    /// Marks the code as part of the expansion of a pseudo-instruction.
+    pub fn expanded_from(mut self, expansion: Expansion) -> Self {
+        self.expansion = Some(expansion);
+        self
+    }
+
+    /// A pad word filling a gap in the instruction memory.
+    pub fn pad(code: u16) -> Self {
+        Code {
+            code,
+            instruction: None,
+            expansion: None,
         }
     }
 
@@ -18,26 +71,47 @@ impl Code {
         self.code
     }
 
+    pub fn get_mnemonic(&self) -> Option<&Mnemonic> {
+        self.instruction
+            .as_ref()
+            .map(|instruction| &instruction.node)
+    }
+
+    pub fn get_expansion(&self) -> Option<&Expansion> {
+        self.expansion.as_ref()
+    }
+
+    /// Returns the span of the source line this code was assembled from.
+    pub fn get_span(&self) -> Option<Span> {
+        self.instruction
+            .as_ref()
+            .map(|instruction| instruction.span)
+    }
+
     pub fn get_line(&self, underscore: bool, mnemonic: bool) -> String {
         let code = if underscore {
-            match self.instruction {
-                Mnemonic::R(_) => {
+            match self.get_mnemonic() {
+                None => {
+                    let (c, x) = self.split_as_j_instr();
+                    format!("{:05b}_{:011b}", c, x)
+                }
+                Some(Mnemonic::R(_)) => {
                     let (d, s, f) = self.split_as_r_instr();
                     format!("{:05b}_{:03b}_{:03b}_{:05b}", 0, d, s, f)
                 }
-                Mnemonic::I(_) => {
+                Some(Mnemonic::I(_)) => {
                     let (c, d, x) = self.split_as_i_instr();
                     format!("{:05b}_{:03b}_{:08b}", c, d, x)
                 }
-                Mnemonic::B(_) => {
+                Some(Mnemonic::B(_)) => {
                     let (c, s, x) = self.split_as_i_instr();
                     format!("{:05b}_{:03b}_{:08b}", c, s, x)
                 }
-                Mnemonic::J(_) => {
+                Some(Mnemonic::J(_)) => {
                     let (c, x) = self.split_as_j_instr();
                     format!("{:05b}_{:011b}", c, x)
                 }
-                Mnemonic::JR(_) => {
+                Some(Mnemonic::JR(_)) => {
                     let (d, _, f) = self.split_as_r_instr();
                     format!("{:05b}_{:03b}_{:03b}_{:05b}", 0, d, 0, f)
                 }
@@ -46,10 +120,22 @@ impl Code {
             format!("{:016b}", self.code)
         };
 
-        let comment = if mnemonic {
-            format!("// {}", self.instruction)
-        } else {
-            "".to_string()
+        // The instruction as written is shown on the first word of its
+        // expansion:
+        //     // LI r0, #4660 => LDHI r0, #18
+        //     //              => ADDIU r0, #52
+        let comment = match (&self.instruction, &self.expansion) {
+            (Some(instruction), Some(expansion)) if mnemonic => {
+                let source = expansion.source.to_string();
+                if expansion.index == 0 {
+                    format!("// {} => {}", source, instruction)
+                } else {
+                    format!("// {:width$} => {}", "", instruction, width = source.len())
+                }
+            }
+            (Some(instruction), None) if mnemonic => format!("// {}", instruction),
+            (None, _) if mnemonic => "// pad".to_string(),
+            _ => "".to_string(),
         };
 
         if underscore {
@@ -82,3 +168,45 @@ impl Code {
         (c, x)
     }
 }
+
+/// A word of the data memory image.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Data {
+    value: u16,
+    span: Span,
+}
+
+impl Data {
+    pub fn new(value: u16, span: Span) -> Self {
+        Data { value, span }
+    }
+
+    pub fn get_value(&self) -> u16 {
+        self.value
+    }
+
+    /// Returns the span of the directive this word was assembled from.
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+
+    pub fn get_line(&self, underscore: bool, value: bool) -> String {
+        let code = if underscore {
+            format!("{:08b}_{:08b}", self.value >> 8, self.value & 0xff)
+        } else {
+            format!("{:016b}", self.value)
+        };
+
+        let comment = if value {
+            format!("// {}", self.value)
+        } else {
+            "".to_string()
+        };
+
+        if underscore {
+            format!("{:<20} {}", code, comment)
+        } else {
+            format!("{:<16} {}", code, comment)
+        }
+    }
+}
@@ -0,0 +1,791 @@
+use crate::{
+    assembler::LabelTable,
+    code::Program,
+    lexer::Lexer,
+    parser::poco::RegisterParser,
+    register::Register,
+    sim::{Machine, Target, Write},
+    source::SourceMap,
+    token::Token,
+};
+use std::{collections::BTreeSet, fmt::Write as _};
+
+/// The commands of the debugger, shown by `help`.
+const HELP: &str = "\
+step [N]             run N instructions (s)
+continue             run until a breakpoint, a watchpoint or the end (c)
+break [ADDR|LABEL]   stop before the instruction at ADDR, or list breakpoints (b)
+delete ADDR|LABEL    remove a breakpoint
+watch [ADDR|LABEL]   stop after writes to the data word at ADDR, or list watchpoints (w)
+unwatch ADDR|LABEL   remove a watchpoint
+regs                 print the registers (r)
+mem ADDR[..LEN]      print LEN data words from ADDR (x)
+disasm [ADDR[..LEN]] print LEN instructions from ADDR, around the current one by default (l)
+step-back [N]        undo N instructions (sb)
+reverse-continue     undo instructions back to a breakpoint or a watchpoint (rc)
+last-write REG|ADDR  print the last instruction that wrote a register or a data word
+where                print the current instruction and its source line
+quit                 leave the debugger (q)";
+
+/// How many instructions `disasm` prints by default.
+const DISASM_LENGTH: u16 = 8;
+
+/// Runs a program step by step on a `Machine`, driven by commands in the
+/// style of gdb.
+///
+/// Addresses are written as numbers or as labels, the labels of the
+/// instruction memory for breakpoints and `disasm`, and those of the data
+/// memory for watchpoints and `mem`. Stepping back needs a machine that
+/// records its history, see `MachineOptions::history`.
+#[derive(Debug)]
+pub struct Debugger<'a> {
+    program: &'a Program,
+    sources: &'a SourceMap,
+    machine: Machine,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<u16>,
+
+    /// The number of instructions `continue` runs before giving up.
+    max_cycles: u64,
+}
+
+/// Why a run of instructions stopped early.
+enum Stop {
+    Breakpoint,
+    Watchpoint {
+        addr: u16,
+        old: u16,
+        new: u16,
+    },
+    Loop,
+
+    /// `continue` ran this many instructions without stopping.
+    Limit(u64),
+
+    /// Nothing is left to undo.
+    Start,
+}
+
+impl<'a> Debugger<'a> {
+    /// Debugs `program` running on `machine`, whose source texts are in
+    /// `sources`.
+    pub fn new(program: &'a Program, sources: &'a SourceMap, machine: Machine) -> Self {
+        Debugger {
+            program,
+            sources,
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            max_cycles: 1_000_000,
+        }
+    }
+
+    pub fn max_cycles(mut self, max_cycles: u64) -> Self {
+        self.max_cycles = max_cycles;
+        self
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Runs a command and returns what it prints, or a message saying why
+    /// the command is wrong. `quit` is left to the caller.
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let arg = words.next();
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected argument `{}`", extra));
+        }
+
+        match (name, arg) {
+            ("help" | "h", None) => Ok(HELP.to_string()),
+            ("step" | "s", count) => {
+                let count = match count {
+                    Some(count) => number(count)?,
+                    None => 1,
+                };
+                Ok(self.resume(u64::from(count), false))
+            }
+            ("continue" | "c", None) => Ok(self.resume(self.max_cycles, true)),
+            ("break" | "b", None) => Ok(self.list(&self.breakpoints, false)),
+            ("break" | "b", Some(addr)) => {
+                let addr = address(addr, &self.program.labels)?;
+                self.breakpoints.insert(addr);
+                Ok(format!("breakpoint at {}", self.name(addr)))
+            }
+            ("delete", Some(addr)) => {
+                let addr = address(addr, &self.program.labels)?;
+                if self.breakpoints.remove(&addr) {
+                    Ok(format!("deleted the breakpoint at {}", self.name(addr)))
+                } else {
+                    Err(format!("no breakpoint at {}", self.name(addr)))
+                }
+            }
+            ("watch" | "w", None) => Ok(self.list(&self.watchpoints, true)),
+            ("watch" | "w", Some(addr)) => {
+                let addr = address(addr, &self.program.data_labels)?;
+                self.machine.read(addr).map_err(|err| err.to_string())?;
+                self.watchpoints.insert(addr);
+                Ok(format!("watchpoint at {}", self.data_name(addr)))
+            }
+            ("unwatch", Some(addr)) => {
+                let addr = address(addr, &self.program.data_labels)?;
+                if self.watchpoints.remove(&addr) {
+                    Ok(format!(
+                        "deleted the watchpoint at {}",
+                        self.data_name(addr)
+                    ))
+                } else {
+                    Err(format!("no watchpoint at {}", self.data_name(addr)))
+                }
+            }
+            ("regs" | "r", None) => Ok(self.registers()),
+            ("mem" | "x", Some(range)) => {
+                let (addr, len) = self.range(range, &self.program.data_labels, 1)?;
+                self.memory(addr, len)
+            }
+            ("disasm" | "l", range) => {
+                let (addr, len) = match range {
+                    Some(range) => self.range(range, &self.program.labels, DISASM_LENGTH)?,
+                    None => {
+                        // Stops at the end of the program.
+                        let addr = self.machine.pc().saturating_sub(2);
+                        let rest = self.program.codes.len().saturating_sub(usize::from(addr));
+                        (addr, DISASM_LENGTH.min(rest as u16))
+                    }
+                };
+                Ok(self.disassemble(addr, len))
+            }
+            ("step-back" | "sb", count) => {
+                let count = match count {
+                    Some(count) => number(count)?,
+                    None => 1,
+                };
+                Ok(self.rewind(u64::from(count)))
+            }
+            ("reverse-continue" | "rc", None) => Ok(self.rewind(u64::MAX)),
+            ("last-write", Some(target)) => self.last_write(target),
+            ("where", None) => Ok(self.location()),
+            ("", None) => Ok(String::new()),
+            ("delete" | "unwatch" | "mem" | "x", None) => {
+                Err(format!("`{}` takes an address", name))
+            }
+            ("last-write", None) => Err("`last-write` takes a register or an address".to_string()),
+            (
+                "help" | "h" | "continue" | "c" | "reverse-continue" | "rc" | "regs" | "r"
+                | "where",
+                Some(arg),
+            ) => Err(format!("unexpected argument `{}`", arg)),
+            _ => Err(format!("unknown command `{}`, see `help`", name)),
+        }
+    }
+
+    /// Runs up to `limit` instructions, stopping after one that writes a
+    /// watched word, jumps to itself or reaches a breakpoint, and describes
+    /// where the program stopped.
+    fn resume(&mut self, limit: u64, report_limit: bool) -> String {
+        let mut output = String::new();
+        let mut stop = None;
+        let mut count = 0;
+        while count < limit {
+            let old: Vec<(u16, u16)> = self
+                .watchpoints
+                .iter()
+                .filter_map(|&addr| self.machine.read(addr).ok().map(|value| (addr, value)))
+                .collect();
+
+            let step = match self.machine.step() {
+                Ok(step) => step,
+                Err(err) => {
+                    writeln!(output, "error: {}", err).unwrap();
+                    break;
+                }
+            };
+            count += 1;
+
+            if let Some(Write::Memory(addr, new)) = step.write {
+                if let Some(&(_, old)) = old.iter().find(|(watched, _)| *watched == addr) {
+                    stop = Some(Stop::Watchpoint { addr, old, new });
+                    break;
+                }
+            }
+            if step.next_pc == step.pc {
+                stop = Some(Stop::Loop);
+                break;
+            }
+            if self.breakpoints.contains(&step.next_pc) {
+                stop = Some(Stop::Breakpoint);
+                break;
+            }
+        }
+
+        if stop.is_none() && report_limit && count == limit {
+            stop = Some(Stop::Limit(count));
+        }
+        self.report(stop, output)
+    }
+
+    /// Undoes up to `limit` instructions, stopping before one that writes a
+    /// watched word or at a breakpoint, and describes where the program
+    /// stopped. Only the instructions the machine remembers can be undone.
+    fn rewind(&mut self, limit: u64) -> String {
+        let mut stop = None;
+        let mut count = 0;
+        while count < limit {
+            let record = match self.machine.undo() {
+                Some(record) => record,
+                None => {
+                    stop = Some(Stop::Start);
+                    break;
+                }
+            };
+            count += 1;
+
+            if let Some(Write::Memory(addr, new)) = record.write {
+                if self.watchpoints.contains(&addr) {
+                    let old = record.old;
+                    stop = Some(Stop::Watchpoint { addr, old, new });
+                    break;
+                }
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                stop = Some(Stop::Breakpoint);
+                break;
+            }
+        }
+
+        self.report(stop, String::new())
+    }
+
+    /// Appends why the program stopped and where to `output`.
+    fn report(&self, stop: Option<Stop>, mut output: String) -> String {
+        match stop {
+            Some(Stop::Breakpoint) => {
+                writeln!(output, "breakpoint at {}", self.name(self.machine.pc())).unwrap()
+            }
+            Some(Stop::Watchpoint { addr, old, new }) => writeln!(
+                output,
+                "watchpoint at {}: {} -> {}",
+                self.data_name(addr),
+                old as i16,
+                new as i16
+            )
+            .unwrap(),
+            Some(Stop::Loop) => writeln!(
+                output,
+                "the program halted: the instruction jumps to itself"
+            )
+            .unwrap(),
+            Some(Stop::Start) => {
+                writeln!(output, "reached the start of the recorded history").unwrap()
+            }
+            Some(Stop::Limit(count)) => {
+                writeln!(output, "stopped after {} instructions", count).unwrap()
+            }
+            None => {}
+        }
+        output.push_str(&self.location());
+        output
+    }
+
+    /// Describes the last remembered instruction that wrote a register or
+    /// a data word.
+    fn last_write(&self, arg: &str) -> Result<String, String> {
+        let target = match RegisterParser::new().parse(&mut Vec::new(), Lexer::new(arg)) {
+            Ok(reg) => Target::Register(reg),
+            Err(_) => Target::Memory(address(arg, &self.program.data_labels)?),
+        };
+        let name = match target {
+            Target::Register(reg) => reg.to_string(),
+            Target::Memory(addr) => self.data_name(addr),
+        };
+
+        match self.machine.last_write(target) {
+            Some((cycle, record)) => Ok(format!(
+                "{} was set from {} to {} in cycle {} by\n{}",
+                name,
+                record.old as i16,
+                record.write.unwrap().value() as i16,
+                cycle,
+                self.describe_instruction(record.pc)
+            )),
+            None => Ok(format!("{} was not written in the recorded history", name)),
+        }
+    }
+
+    /// Describes the current instruction, with the source line it was
+    /// assembled from.
+    fn location(&self) -> String {
+        self.describe_instruction(self.machine.pc())
+    }
+
+    /// Describes the instruction at `pc`, with the source line it was
+    /// assembled from.
+    fn describe_instruction(&self, pc: u16) -> String {
+        let mut output = self.disassemble(pc, 1);
+
+        let span = self
+            .program
+            .codes
+            .get(usize::from(pc))
+            .and_then(|code| code.get_span());
+        if let Some(span) = span {
+            let line = self.sources.get(span.start.file).and_then(|file| {
+                let line = file.source.lines().nth(span.start.line as usize - 1)?;
+                Some((file.name.as_str(), line.trim()))
+            });
+            if let Some((name, line)) = line {
+                write!(output, "\n    {}:{}: {}", name, span.start.line, line).unwrap();
+            }
+        }
+
+        output
+    }
+
+    fn registers(&self) -> String {
+        let mut output = format!(
+            "pc = {:#06x}, cycles = {}",
+            self.machine.pc(),
+            self.machine.cycles()
+        );
+        for reg in Register::ALL.iter() {
+            let value = self.machine.register(*reg);
+            write!(output, "\n{}  = {:#06x} {:>6}", reg, value, value as i16).unwrap();
+        }
+
+        output
+    }
+
+    fn memory(&self, addr: u16, len: u16) -> Result<String, String> {
+        let mut lines = Vec::new();
+        for offset in 0..len {
+            let addr = addr.wrapping_add(offset);
+            let value = self.machine.read(addr).map_err(|err| err.to_string())?;
+            lines.push(format!(
+                "{:<16} = {:#06x} {:>6}",
+                self.data_name(addr),
+                value,
+                value as i16
+            ));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Lists `len` instructions from `addr`, marking the current one with
+    /// `=>` and breakpoints with `*`.
+    fn disassemble(&self, addr: u16, len: u16) -> String {
+        let mut lines = Vec::new();
+        for offset in 0..len {
+            let addr = addr.wrapping_add(offset);
+            let code = match self.machine.fetch(addr) {
+                Ok(code) => code,
+                Err(_) => break,
+            };
+            let text = match self.program.codes.get(usize::from(addr)) {
+                Some(code) => match code.get_mnemonic() {
+                    Some(mnemonic) => mnemonic.to_string(),
+                    None => "pad".to_string(),
+                },
+                None => String::new(),
+            };
+
+            let marker = if addr == self.machine.pc() {
+                "=>"
+            } else {
+                "  "
+            };
+            let breakpoint = if self.breakpoints.contains(&addr) {
+                "*"
+            } else {
+                " "
+            };
+            lines.push(format!(
+                "{}{} {:<16} {:016b}  {}",
+                marker,
+                breakpoint,
+                self.name(addr),
+                code,
+                text
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Lists breakpoints, or watchpoints if `data` is set.
+    fn list(&self, addresses: &BTreeSet<u16>, data: bool) -> String {
+        if addresses.is_empty() {
+            return "none".to_string();
+        }
+
+        addresses
+            .iter()
+            .map(|&addr| {
+                if data {
+                    self.data_name(addr)
+                } else {
+                    self.name(addr)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Names an instruction address after the label at or before it:
+    /// `0x0005 <loop+1>`.
+    fn name(&self, addr: u16) -> String {
+        describe(addr, &self.program.labels, self.program.codes.len())
+    }
+
+    fn data_name(&self, addr: u16) -> String {
+        describe(addr, &self.program.data_labels, self.program.data.len())
+    }
+
+    /// Parses `ADDR[..LEN]`, with `default_len` when `LEN` is left out.
+    fn range(
+        &self,
+        range: &str,
+        labels: &LabelTable,
+        default_len: u16,
+    ) -> Result<(u16, u16), String> {
+        match range.split_once("..") {
+            Some((addr, len)) => Ok((address(addr, labels)?, number(len)?)),
+            None => Ok((address(range, labels)?, default_len)),
+        }
+    }
+}
+
+/// Names `addr` after the label at or before it, if it is in the image of
+/// `len` words the labels belong to.
+fn describe(addr: u16, labels: &LabelTable, len: usize) -> String {
+    // Labels with `@` are made up by the assembler, see `scope_labels`.
+    let label = labels
+        .iter()
+        .filter(|(label, &at)| !label.contains('@') && at <= addr)
+        .filter(|_| usize::from(addr) < len)
+        .max_by_key(|(label, &at)| (at, std::cmp::Reverse(label.as_str())));
+
+    match label {
+        Some((label, &at)) if at == addr => format!("{:#06x} <{}>", addr, label),
+        Some((label, &at)) => format!("{:#06x} <{}+{}>", addr, label, addr - at),
+        None => format!("{:#06x}", addr),
+    }
+}
+
+/// Parses an address written as a number or as one of `labels`.
+fn address(s: &str, labels: &LabelTable) -> Result<u16, String> {
+    let mut lexer = Lexer::new(s);
+    match (lexer.next_token(), lexer.next_token()) {
+        (Ok(Some(Token::Ident(label))), Ok(None)) => match labels.get(&label) {
+            Some(&addr) => Ok(addr),
+            None => Err(format!("undefined label `{}`", label)),
+        },
+        _ => number(s),
+    }
+}
+
+/// Parses a number written like in the source: `16`, `0x10`.
+fn number(s: &str) -> Result<u16, String> {
+    let mut lexer = Lexer::new(s);
+    match (lexer.next_token(), lexer.next_token()) {
+        (Ok(Some(Token::Num(value))), Ok(None)) if (0..=0xffff).contains(&value) => {
+            Ok(value as u16)
+        }
+        _ => Err(format!("`{}` is not an address", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble_items_with, parser::parse_source, sim::MachineOptions};
+
+    const MULT: &str = "\
+LDIU r0, #2
+LD r1, (r0)
+LDIU r0, #3
+LD r2, (r0)
+:loop
+ADD r3, r1
+ADDI r2, #-1
+BNZ r2, loop
+LDIU r0, #0
+ST r3, (r0)
+:end
+BEZ r2, end
+";
+
+    fn program(source: &str) -> (Program, SourceMap) {
+        let mut sources = SourceMap::new();
+        let file = sources.add("mult.asm", source, None);
+        let items = parse_source(&mut sources, file, &Default::default(), Default::default());
+        let program = assemble_items_with(items.unwrap(), Default::default()).unwrap();
+        (program, sources)
+    }
+
+    fn debugger<'a>(program: &'a Program, sources: &'a SourceMap) -> Debugger<'a> {
+        let options = MachineOptions {
+            history: 100,
+            ..MachineOptions::default()
+        };
+        let mut machine = Machine::with_program(program, options).unwrap();
+        machine.load_data(2, &[5, 7]).unwrap();
+        Debugger::new(program, sources, machine)
+    }
+
+    #[test]
+    fn step() {
+        let (program, sources) = program(MULT);
+        let mut debugger = debugger(&program, &sources);
+
+        assert_eq!(
+            debugger.execute("step").unwrap(),
+            "=>  0x0001           0000000100001001  LD r1, (r0)\n    mult.asm:2: LD r1, (r0)"
+        );
+        assert_eq!(
+            debugger.execute("s 4").unwrap(),
+            "=>  0x0005 <loop+1>  0110001011111111  ADDI r2, #-1\n    mult.asm:7: ADDI r2, #-1"
+        );
+        assert_eq!(debugger.machine().register(Register::R3), 5);
+
+        let regs = debugger.execute("regs").unwrap();
+        assert!(regs.starts_with("pc = 0x0005, cycles = 5\nr0  = 0x0003      3\n"));
+
+        assert_eq!(
+            debugger.execute("continue").unwrap(),
+            "the program halted: the instruction jumps to itself\n".to_string()
+                + "=>  0x0009 <end>     1000001000000000  BEZ r2, end\n"
+                + "    mult.asm:12: BEZ r2, end"
+        );
+        assert_eq!(debugger.machine().read(0).unwrap(), 35);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (program, sources) = program(MULT);
+        let mut debugger = debugger(&program, &sources);
+
+        assert_eq!(debugger.execute("break").unwrap(), "none");
+        assert_eq!(
+            debugger.execute("b loop").unwrap(),
+            "breakpoint at 0x0004 <loop>"
+        );
+        assert_eq!(
+            debugger.execute("break 0x6").unwrap(),
+            "breakpoint at 0x0006 <loop+2>"
+        );
+        assert_eq!(
+            debugger.execute("break").unwrap(),
+            "0x0004 <loop>\n0x0006 <loop+2>"
+        );
+
+        assert!(debugger
+            .execute("c")
+            .unwrap()
+            .starts_with("breakpoint at 0x0004 <loop>\n=>* 0x0004 <loop>"));
+        assert!(debugger
+            .execute("c")
+            .unwrap()
+            .starts_with("breakpoint at 0x0006 <loop+2>\n"));
+        assert_eq!(
+            debugger.execute("delete 6").unwrap(),
+            "deleted the breakpoint at 0x0006 <loop+2>"
+        );
+        assert_eq!(
+            debugger.execute("delete 6").unwrap_err(),
+            "no breakpoint at 0x0006 <loop+2>"
+        );
+
+        // A step stops at a breakpoint too.
+        assert!(debugger
+            .execute("step 10")
+            .unwrap()
+            .starts_with("breakpoint at 0x0004 <loop>\n"));
+        assert_eq!(debugger.machine().cycles(), 7);
+
+        debugger.execute("delete loop").unwrap();
+        assert!(debugger
+            .execute("continue")
+            .unwrap()
+            .starts_with("the program halted: the instruction jumps to itself\n"));
+        assert_eq!(debugger.machine().read(0).unwrap(), 35);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let (program, sources) = program(
+            ".data
+            :result
+            .word 0
+            .text
+            LDI r0, #0
+            LDI r1, #5
+            ST r1, (r0)
+            ST r1, (r0)
+            :end
+            JMP end",
+        );
+        let mut debugger = debugger(&program, &sources);
+
+        assert_eq!(
+            debugger.execute("watch result").unwrap(),
+            "watchpoint at 0x0000 <result>"
+        );
+        assert_eq!(debugger.execute("w").unwrap(), "0x0000 <result>");
+        assert!(debugger
+            .execute("c")
+            .unwrap()
+            .starts_with("watchpoint at 0x0000 <result>: 0 -> 5\n=>  0x0003"));
+
+        // Every write stops, even of the same value.
+        assert!(debugger
+            .execute("c")
+            .unwrap()
+            .starts_with("watchpoint at 0x0000 <result>: 5 -> 5\n"));
+        debugger.execute("unwatch 0").unwrap();
+        assert!(debugger
+            .execute("c")
+            .unwrap()
+            .starts_with("the program halted"));
+
+        assert_eq!(
+            debugger.execute("mem result..2").unwrap(),
+            "0x0000 <result>  = 0x0005      5\n0x0001           = 0x0000      0"
+        );
+        assert_eq!(
+            debugger.execute("x 0xffff").unwrap(),
+            "0xffff           = 0x0000      0"
+        );
+    }
+
+    #[test]
+    fn disasm() {
+        let (program, sources) = program(MULT);
+        let mut debugger = debugger(&program, &sources);
+        debugger.execute("break end").unwrap();
+        debugger.execute("step 3").unwrap();
+
+        assert_eq!(
+            debugger.execute("disasm").unwrap(),
+            [
+                "    0x0001           0000000100001001  LD r1, (r0)",
+                "    0x0002           0100100000000011  LDIU r0, #3",
+                "=>  0x0003           0000001000001001  LD r2, (r0)",
+                "    0x0004 <loop>    0000001100100110  ADD r3, r1",
+                "    0x0005 <loop+1>  0110001011111111  ADDI r2, #-1",
+                "    0x0006 <loop+2>  1000101011111110  BNZ r2, loop",
+                "    0x0007 <loop+3>  0100100000000000  LDIU r0, #0",
+                "    0x0008 <loop+4>  0000001100001000  ST r3, (r0)",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            debugger.execute("l end..1").unwrap(),
+            "  * 0x0009 <end>     1000001000000000  BEZ r2, end"
+        );
+        assert!(debugger
+            .execute("where")
+            .unwrap()
+            .ends_with("mult.asm:4: LD r2, (r0)"));
+    }
+
+    #[test]
+    fn errors() {
+        let (program, sources) = program(MULT);
+        let mut debugger = debugger(&program, &sources);
+
+        assert_eq!(
+            debugger.execute("jump 3").unwrap_err(),
+            "unknown command `jump`, see `help`"
+        );
+        assert_eq!(
+            debugger.execute("b nowhere").unwrap_err(),
+            "undefined label `nowhere`"
+        );
+        assert_eq!(
+            debugger.execute("b 0x10000").unwrap_err(),
+            "`0x10000` is not an address"
+        );
+        assert_eq!(
+            debugger.execute("regs r0").unwrap_err(),
+            "unexpected argument `r0`"
+        );
+        assert_eq!(
+            debugger.execute("mem").unwrap_err(),
+            "`mem` takes an address"
+        );
+        assert_eq!(debugger.execute("").unwrap(), "");
+
+        // The labels of one memory are not addresses in the other.
+        assert_eq!(
+            debugger.execute("watch loop").unwrap_err(),
+            "undefined label `loop`"
+        );
+
+        let mut machine = Machine::new(MachineOptions::default());
+        machine.load_code(0, &[0b11111_000_00000000]).unwrap();
+        let mut debugger = Debugger::new(&program, &sources, machine);
+        assert!(debugger
+            .execute("step")
+            .unwrap()
+            .starts_with("error: invalid instruction `1111100000000000` at address `0`\n"));
+        assert_eq!(debugger.machine().cycles(), 0);
+    }
+
+    #[test]
+    fn reverse() {
+        let (program, sources) = program(MULT);
+        let mut debugger = debugger(&program, &sources);
+        debugger.execute("continue").unwrap();
+        assert_eq!(debugger.machine().cycles(), 28);
+
+        assert_eq!(
+            debugger.execute("last-write r3").unwrap(),
+            "r3 was set from 30 to 35 in cycle 22 by\n".to_string()
+                + "    0x0004 <loop>    0000001100100110  ADD r3, r1\n"
+                + "    mult.asm:6: ADD r3, r1"
+        );
+        assert!(debugger
+            .execute("last-write 0")
+            .unwrap()
+            .starts_with("0x0000 was set from 0 to 35 in cycle 26 by\n"));
+        assert_eq!(
+            debugger.execute("last-write r5").unwrap(),
+            "r5 was not written in the recorded history"
+        );
+
+        assert!(debugger
+            .execute("step-back 2")
+            .unwrap()
+            .starts_with("=>  0x0008 <loop+4>"));
+        assert_eq!(debugger.machine().read(0).unwrap(), 0);
+
+        // Stops before the instruction that writes a watched word.
+        debugger.execute("step").unwrap();
+        debugger.execute("watch 0").unwrap();
+        debugger.execute("step").unwrap();
+        assert!(debugger
+            .execute("rc")
+            .unwrap()
+            .starts_with("watchpoint at 0x0000: 0 -> 35\n=>  0x0008"));
+
+        debugger.execute("unwatch 0").unwrap();
+        debugger.execute("break loop").unwrap();
+        assert!(debugger
+            .execute("reverse-continue")
+            .unwrap()
+            .starts_with("breakpoint at 0x0004 <loop>\n"));
+        assert_eq!(debugger.machine().register(Register::R3), 30);
+
+        debugger.execute("delete loop").unwrap();
+        assert!(debugger
+            .execute("rc")
+            .unwrap()
+            .starts_with("reached the start of the recorded history\n=>  0x0000"));
+        assert_eq!(debugger.machine().cycles(), 0);
+        assert_eq!(debugger.machine().registers(), &[0; 8]);
+    }
+}
@@ -0,0 +1,747 @@
+use crate::{
+    error::{Error, Warning},
+    source::{Origin, SourceMap},
+    span::{FileId, Span},
+    token::Token,
+};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Level {
+    fn color(&self) -> &'static str {
+        match self {
+            Level::Error => RED,
+            Level::Warning => YELLOW,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A message attached to a span of the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new<S: Into<String>>(span: Span, message: S) -> Self {
+        Label {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// A report about the source in the style of rustc.
+///
+/// The first label is the primary one: it is shown in the ` --> file:line:column`
+/// header and underlined with `^`, while the others are underlined with `-`.
+/// The source lines are shown in their order in each file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+/// How many macro calls are shown for a label in a macro expansion.
+const MAX_EXPANSIONS: usize = 4;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(level: Level, message: S) -> Self {
+        Diagnostic {
+            level,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn error<S: Into<String>>(message: S) -> Self {
+        Diagnostic::new(Level::Error, message)
+    }
+
+    pub fn warning<S: Into<String>>(message: S) -> Self {
+        Diagnostic::new(Level::Warning, message)
+    }
+
+    pub fn with_label<S: Into<String>>(mut self, span: Span, message: S) -> Self {
+        self.labels.push(Label::new(span, message));
+        self
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Renders the diagnostic for the source `source` read from `file_name`.
+    ///
+    /// When `color` is set the output contains ANSI escape sequences.
+    pub fn render(&self, file_name: &str, source: &str, color: bool) -> String {
+        let mut sources = SourceMap::new();
+        sources.add(file_name, source, None);
+
+        self.render_sources(&sources, color)
+    }
+
+    /// Renders the diagnostic for a program read from several files, looking
+    /// the file of each label up in `sources`.
+    ///
+    /// When the primary label is in an included file or a macro expansion,
+    /// the `.include` directives that read it are shown as notes and the
+    /// macro calls that expanded it as more labels.
+    pub fn render_sources(&self, sources: &SourceMap, color: bool) -> String {
+        let file_name = |file: FileId| sources.get(file).map_or("<unknown>", |f| f.name.as_str());
+
+        let mut labels = self.labels.clone();
+        let mut notes = Vec::new();
+        if let Some(primary) = self.labels.first() {
+            let mut hidden = 0;
+            for origin in sources.backtrace(primary.span.start.file) {
+                match origin {
+                    Origin::Macro { .. } if labels.len() >= self.labels.len() + MAX_EXPANSIONS => {
+                        hidden += 1
+                    }
+                    Origin::Include(span) => notes.push(format!(
+                        "in file included from {}:{}",
+                        file_name(span.start.file),
+                        span.start
+                    )),
+                    Origin::Macro { name, call_site } => labels.push(Label::new(
+                        *call_site,
+                        format!("in this expansion of `{}`", name),
+                    )),
+                }
+            }
+            if hidden > 0 {
+                notes.push(format!("{} more macro expansions are not shown", hidden));
+            }
+        }
+        notes.extend(self.notes.iter().cloned());
+
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut out = format!(
+            "{}{}\n",
+            paint(self.level.color(), &self.level.to_string()),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        let width = labels
+            .iter()
+            .map(|label| label.span.start.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(width);
+        let bar = paint(BLUE, "|");
+
+        if let Some(primary) = labels.first() {
+            out += &format!(
+                "{}{} {}:{}\n",
+                gutter,
+                paint(BLUE, "-->"),
+                file_name(primary.span.start.file),
+                primary.span.start
+            );
+            out += &format!("{} {}\n", gutter, bar);
+
+            // Like rustc, show the lines of each file in order, starting with
+            // the file of the primary label.
+            let mut files = Vec::new();
+            for label in &labels {
+                if !files.contains(&label.span.start.file) {
+                    files.push(label.span.start.file);
+                }
+            }
+            let mut order: Vec<usize> = (0..labels.len()).collect();
+            order.sort_by_key(|&index| {
+                let start = labels[index].span.start;
+                let file = files.iter().position(|&file| file == start.file);
+                (file, start.line, start.column)
+            });
+
+            for (position, &index) in order.iter().enumerate() {
+                let label = &labels[index];
+                let start = label.span.start;
+
+                // A label in another file than the one before gets its own header.
+                if position > 0
+                    && file_name(start.file)
+                        != file_name(labels[order[position - 1]].span.start.file)
+                {
+                    out += &format!(
+                        "{}{} {}:{}\n",
+                        gutter,
+                        paint(BLUE, ":::"),
+                        file_name(start.file),
+                        start
+                    );
+                }
+
+                let source = sources.get(start.file).map_or("", |f| &f.source);
+                let line = source
+                    .lines()
+                    .nth(start.line.saturating_sub(1) as usize)
+                    .unwrap_or("");
+
+                // Keep tabs so that the underline lines up with the source line.
+                let indent: String = line
+                    .chars()
+                    .take(start.column.saturating_sub(1) as usize)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+
+                let line_len = line.chars().count() as u32 + 1;
+                let end_column = if label.span.end.line == start.line {
+                    label.span.end.column
+                } else {
+                    line_len
+                };
+                let len = end_column.saturating_sub(start.column).max(1) as usize;
+
+                let (mark, style) = if index == 0 {
+                    ("^", self.level.color())
+                } else {
+                    ("-", BLUE)
+                };
+                let underline = mark.repeat(len);
+                let underline = if label.message.is_empty() {
+                    underline
+                } else {
+                    format!("{} {}", underline, label.message)
+                };
+
+                out += &format!(
+                    "{} {} {}\n",
+                    paint(BLUE, &format!("{:>width$}", start.line, width = width)),
+                    bar,
+                    line
+                );
+                out += &format!(
+                    "{} {} {}{}\n",
+                    gutter,
+                    bar,
+                    indent,
+                    paint(style, &underline)
+                );
+            }
+        }
+
+        if !labels.is_empty() && (!notes.is_empty() || self.help.is_some()) {
+            out += &format!("{} {}\n", gutter, bar);
+        }
+
+        for note in &notes {
+            out += &format!(
+                "{} {} {} {}\n",
+                gutter,
+                paint(BLUE, "="),
+                paint(BOLD, "note:"),
+                note
+            );
+        }
+
+        if let Some(help) = &self.help {
+            out += &format!(
+                "{} {} {} {}\n",
+                gutter,
+                paint(BLUE, "="),
+                paint(BOLD, "help:"),
+                help
+            );
+        }
+
+        out
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(err: &Error) -> Self {
+        let diagnostic = Diagnostic::error(err.to_string());
+
+        match err {
+            Error::InvalidToken { span } => diagnostic
+                .with_label(*span, "invalid token")
+                .with_help("labels and mnemonics may only contain letters, digits and `_`"),
+            Error::UnterminatedComment { span } => diagnostic
+                .with_label(*span, "comment starts here")
+                .with_help("close the comment with `*/`"),
+            Error::InvalidNumber { span } => {
+                diagnostic.with_label(*span, "invalid number").with_help(
+                    "numbers are written in decimal, with a `0x`, `0b` or `0o` prefix, \
+                     or as a character like `'A'`",
+                )
+            }
+            Error::InvalidString { span } => {
+                diagnostic.with_label(*span, "invalid string").with_help(
+                    "close the string with `\"` on the same line; the escapes are \
+                     `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\'` and `\\\"`",
+                )
+            }
+            Error::UnrecognizedToken {
+                token: Token::Ident(word),
+                span,
+                expected,
+            } if keyword_in_other_case(word, expected).is_some() => {
+                let keyword = keyword_in_other_case(word, expected).unwrap();
+                diagnostic
+                    .with_label(*span, "unexpected token")
+                    .with_help(format!(
+                        "keywords are case-sensitive with `--strict-case`; write `{}`",
+                        keyword
+                    ))
+            }
+            Error::UnrecognizedToken {
+                token: Token::Newline,
+                span,
+                expected,
+            } => with_expected(
+                diagnostic.with_label(*span, "unexpected end of line"),
+                expected,
+            ),
+            Error::UnrecognizedToken { span, expected, .. } => {
+                with_expected(diagnostic.with_label(*span, "unexpected token"), expected)
+            }
+            Error::UnrecognizedEof { location, expected } => with_expected(
+                diagnostic.with_label(Span::new(*location, *location), "unexpected end of file"),
+                expected,
+            ),
+            Error::ExtraToken { span, .. } => diagnostic
+                .with_label(*span, "extra token")
+                .with_help("remove this token"),
+            Error::ImmediateOutOfRange { span, min, max, .. } => diagnostic
+                .with_label(*span, "immediate out of range")
+                .with_help(format!(
+                    "the immediate must be in the range `{}..={}`",
+                    min, max
+                )),
+            Error::ValueOutOfRange { span, min, max, .. } => diagnostic
+                .with_label(*span, "value out of range")
+                .with_help(format!(
+                    "the value must be in the range `{}..={}`",
+                    min, max
+                )),
+            Error::WrongSection { section, span } => diagnostic
+                .with_label(*span, format!("belongs in `{}`", section))
+                .with_help(format!("switch sections with `{}`", section)),
+            Error::UndefinedLabel { label, span } => {
+                let anonymous = |direction| {
+                    label
+                        .strip_suffix(direction)
+                        .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                };
+                let help = if let Some(n) = anonymous('b') {
+                    format!("define an anonymous label `:{}` before this line", n)
+                } else if let Some(n) = anonymous('f') {
+                    format!("define an anonymous label `:{}` after this line", n)
+                } else {
+                    match label.split_once('.') {
+                        Some((scope, local)) if !scope.is_empty() => format!(
+                            "define the local label with `:.{}` after `:{}`",
+                            local, scope
+                        ),
+                        _ => format!("define the label with `:{}`", label),
+                    }
+                };
+
+                diagnostic.with_label(*span, "used here").with_help(help)
+            }
+            Error::UnknownLabel { option, .. } => diagnostic.with_help(format!(
+                "pass `{}` an address or a label of the program",
+                option
+            )),
+            Error::UndefinedSymbol { symbol, span } => diagnostic
+                .with_label(*span, "used here")
+                .with_help(format!("define the symbol with `.equ {}, value`", symbol)),
+            Error::UnknownFunction { span, .. } => diagnostic
+                .with_label(*span, "not a function")
+                .with_help("the functions are `lo(x)` and `hi(x)`"),
+            Error::DivisionByZero { span } => diagnostic.with_label(*span, "divides by zero"),
+            Error::ArithmeticOverflow { span } => diagnostic
+                .with_label(*span, "overflows")
+                .with_note("constant expressions are evaluated with 64-bit integers"),
+            Error::DuplicateLabel { span, previous, .. } => diagnostic
+                .with_label(*span, "redefined here")
+                .with_label(*previous, "first defined here")
+                .with_help("rename one of the labels"),
+            Error::SymbolConflict { span, previous, .. } => diagnostic
+                .with_label(*span, "redefined here")
+                .with_label(*previous, "first defined here")
+                .with_help("rename the label or the symbol"),
+            Error::Overlap { span, previous, .. } => diagnostic
+                .with_label(*span, "placed here")
+                .with_label(*previous, "first used here")
+                .with_help("move the `.org` so that the regions do not overlap"),
+            Error::AddressOverflow { span, .. } => diagnostic
+                .with_label(*span, "placed past address `65535`")
+                .with_help("move the `.org` lower, or make the section smaller"),
+            Error::IncludeNotFound { span, .. } => {
+                diagnostic.with_label(*span, "included here").with_help(
+                    "paths are relative to the including file; add directories to search with `-I`",
+                )
+            }
+            Error::IncludeCycle { span, .. } => diagnostic
+                .with_label(*span, "included again here")
+                .with_help("remove one of the `.include` directives in the cycle"),
+            Error::UnmatchedDirective {
+                directive: Token::Macro,
+                span,
+            } => diagnostic
+                .with_label(*span, "macro starts here")
+                .with_help("end the macro with `.endm` in the same file"),
+            Error::UnmatchedDirective {
+                directive: Token::If,
+                span,
+            }
+            | Error::UnmatchedDirective {
+                directive: Token::Ifdef,
+                span,
+            }
+            | Error::UnmatchedDirective {
+                directive: Token::Ifndef,
+                span,
+            } => diagnostic
+                .with_label(*span, "condition starts here")
+                .with_help("end the condition with `.endif` in the same file or macro"),
+            Error::UnmatchedDirective { span, .. } => {
+                diagnostic.with_label(*span, "no matching directive")
+            }
+            Error::DuplicateMacro { span, previous, .. } => diagnostic
+                .with_label(*span, "redefined here")
+                .with_label(*previous, "first defined here")
+                .with_help("rename one of the macros"),
+            Error::MacroArguments { span, .. } => diagnostic
+                .with_label(*span, "called here")
+                .with_help("separate the arguments with `,`"),
+            Error::MacroRecursion { span, .. } => diagnostic
+                .with_label(*span, "called here")
+                .with_note(format!(
+                    "macros may be nested at most {} deep",
+                    crate::preprocessor::MAX_DEPTH
+                )),
+            Error::BranchOutOfRange { span, min, max, .. } => diagnostic
+                .with_label(*span, "target out of range")
+                .with_note(format!(
+                    "the target must be within `{}..={}` words of this instruction",
+                    min, max
+                ))
+                .with_help("move the target closer, or pass `--relax` to lengthen the branch"),
+            Error::InvalidInstruction { .. }
+            | Error::AddressOutOfRange { .. }
+            | Error::InvalidTrace { .. }
+            | Error::Io(_)
+            | Error::Multiple(_) => diagnostic,
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        let diagnostic = Diagnostic::warning(warning.to_string());
+
+        match warning {
+            Warning::UnusedLabel { label, span } => diagnostic
+                .with_label(*span, "defined here")
+                .with_help(format!(
+                    "if this is intentional, prefix it with an underscore: `_{}`",
+                    label
+                )),
+        }
+    }
+}
+
+/// Returns the keyword that `word` spells in a different case, if any.
+///
+/// A pseudo-instruction name is only a keyword where a mnemonic is
+/// `expected`; elsewhere it is a label in any case.
+fn keyword_in_other_case(word: &str, expected: &[String]) -> Option<Token> {
+    Token::keyword(&word.to_ascii_uppercase())
+        .or_else(|| Token::keyword(&word.to_ascii_lowercase()))
+        .filter(|keyword| !keyword.is_pseudo() || expected.iter().any(|e| e == r#""NOP""#))
+}
+
+fn with_expected(diagnostic: Diagnostic, expected: &[String]) -> Diagnostic {
+    let expected = describe_expected(expected);
+
+    let diagnostic = match expected.as_slice() {
+        [] => diagnostic,
+        [one] => diagnostic.with_note(format!("expected {}", one)),
+        _ => diagnostic.with_note(format!("expected one of {}", expected.join(", "))),
+    };
+
+    if expected.iter().any(|e| e == "register") {
+        diagnostic.with_help("registers are written `r0` through `r7`")
+    } else if expected.iter().any(|e| e == "`#`") {
+        diagnostic.with_help("immediates are written with a leading `#`, e.g. `#1`")
+    } else if expected.iter().any(|e| e == "label") {
+        diagnostic.with_help("branches and jumps take a label name, e.g. `JMP loop`")
+    } else if expected.iter().any(|e| e == "end of line") {
+        diagnostic.with_help("each instruction must be on its own line")
+    } else {
+        diagnostic
+    }
+}
+
+/// Converts the terminal names reported by LALRPOP into readable descriptions,
+/// folding the register terminals into a single entry.
+fn describe_expected(expected: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+
+    for terminal in expected {
+        let name = terminal.trim_matches('"');
+        let description = match name {
+            "Num" => "number".to_string(),
+            "Ident" => "label".to_string(),
+            "Str" => "string".to_string(),
+            "Newline" => "end of line".to_string(),
+            "R0" | "R1" | "R2" | "R3" | "R4" | "R5" | "R6" | "R7" => "register".to_string(),
+            _ => format!("`{}`", name),
+        };
+
+        if !result.contains(&description) {
+            result.push(description);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::*, preprocessor::Environment, span::Location};
+
+    #[test]
+    fn render_unrecognized_token() {
+        let source = "ADD r0, r1\nSUB r0 r1\n";
+        let err = parse_file(source).unwrap_err();
+
+        let expected = r"error: unexpected token `r1`
+ --> test.asm:2:8
+  |
+2 | SUB r0 r1
+  |        ^^ unexpected token
+  |
+  = note: expected `,`
+";
+        assert_eq!(
+            Diagnostic::from(&err).render("test.asm", source, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_expected_register() {
+        let source = "MV r0, #1";
+        let err = parse_file(source).unwrap_err();
+
+        let expected = r"error: unexpected token `#`
+ --> test.asm:1:8
+  |
+1 | MV r0, #1
+  |        ^ unexpected token
+  |
+  = note: expected register
+  = help: registers are written `r0` through `r7`
+";
+        assert_eq!(
+            Diagnostic::from(&err).render("test.asm", source, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_pseudo_name() {
+        // Outside the place of a mnemonic a pseudo-instruction name is a
+        // label, so the case is not the problem.
+        let source = "ADD r0, r1 push r2\n";
+        let err = parse_file(source).unwrap_err();
+
+        let rendered = Diagnostic::from(&err).render("test.asm", source, false);
+        assert!(rendered.starts_with("error: unexpected token `push`\n"));
+        assert!(!rendered.contains("--strict-case"));
+
+        let strict = ParseOptions { strict_case: true };
+        let err = parse_file_with("push r2\n", strict).unwrap_err();
+        assert!(Diagnostic::from(&err)
+            .render("test.asm", "push r2\n", false)
+            .contains("write `PUSH`"));
+    }
+
+    #[test]
+    fn render_two_instructions() {
+        let source = "ADD r0, r1 SUB r2, r3\n";
+        let err = parse_file(source).unwrap_err();
+
+        let expected = r"error: unexpected token `SUB`
+ --> test.asm:1:12
+  |
+1 | ADD r0, r1 SUB r2, r3
+  |            ^^^ unexpected token
+  |
+  = note: expected end of line
+  = help: each instruction must be on its own line
+";
+        assert_eq!(
+            Diagnostic::from(&err).render("test.asm", source, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_without_source() {
+        let err = Error::UnknownLabel {
+            label: "done".to_string(),
+            option: "--halt".to_string(),
+        };
+
+        let expected = r"error: undefined label `done`
+ = help: pass `--halt` an address or a label of the program
+";
+        assert_eq!(
+            Diagnostic::from(&err).render_sources(&SourceMap::new(), false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_secondary_label() {
+        let source = ":loop\nNOP\n:loop\n";
+        let first = Span::new(Location::new(0, 1, 1), Location::new(5, 1, 6));
+        let second = Span::new(Location::new(10, 3, 1), Location::new(15, 3, 6));
+        let err = Error::DuplicateLabel {
+            label: "loop".to_string(),
+            span: second,
+            previous: first,
+        };
+
+        let expected = r"error: label `loop` is defined multiple times
+ --> test.asm:3:1
+  |
+1 | :loop
+  | ----- first defined here
+3 | :loop
+  | ^^^^^ redefined here
+  |
+  = help: rename one of the labels
+";
+        assert_eq!(
+            Diagnostic::from(&err).render("test.asm", source, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_included() {
+        let mut sources = SourceMap::new();
+        let main = sources.add("main.asm", "NOP\n.include \"lib.asm\"\n", None);
+        let include = Span::new(Location::new(4, 2, 1), Location::new(22, 2, 19));
+        let lib = sources.add("lib.asm", ":f\n:f\n", Some(Origin::Include(include)));
+
+        let at = |line: u32| {
+            let mut start = Location::new(0, line, 1);
+            start.file = lib;
+            let mut end = Location::new(0, line, 3);
+            end.file = lib;
+            Span::new(start, end)
+        };
+        let err = Error::DuplicateLabel {
+            label: "f".to_string(),
+            span: at(2),
+            previous: at(1),
+        };
+        assert_eq!(main, FileId(0));
+
+        let expected = r"error: label `f` is defined multiple times
+ --> lib.asm:2:1
+  |
+1 | :f
+  | -- first defined here
+2 | :f
+  | ^^ redefined here
+  |
+  = note: in file included from main.asm:2:1
+  = help: rename one of the labels
+";
+        assert_eq!(
+            Diagnostic::from(&err).render_sources(&sources, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_expansion() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("main.asm", ".macro m n\nLDI r0, #n\n.endm\nm 300\n", None);
+        let err = parse_source(
+            &mut sources,
+            file,
+            &Environment::default(),
+            ParseOptions::default(),
+        )
+        .and_then(crate::assembler::assemble_items)
+        .unwrap_err();
+
+        let expected = r"error: immediate `300` is out of range `-128..=127`
+ --> main.asm:2:1
+  |
+2 | LDI r0, #n
+  | ^^^^^^^^^^ immediate out of range
+4 | m 300
+  | ----- in this expansion of `m`
+  |
+  = help: the immediate must be in the range `-128..=127`
+";
+        assert_eq!(
+            Diagnostic::from(&err).render_sources(&sources, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_without_span() {
+        let err = Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "not found",
+        ));
+        assert_eq!(
+            Diagnostic::from(&err).render("test.asm", "", false),
+            "error: not found\n"
+        );
+    }
+}
@@ -0,0 +1,477 @@
+use crate::{
+    mnemonic::Section,
+    span::{Location, Span},
+    token::Token,
+};
+use lalrpop_util::ParseError;
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A character that does not start any token: `@`
+    InvalidToken {
+        span: Span,
+    },
+
+    /// A `/*` comment without a closing `*/`.
+    UnterminatedComment {
+        span: Span,
+    },
+
+    /// A string literal with an unknown escape or without a closing quote.
+    InvalidString {
+        span: Span,
+    },
+
+    /// A malformed number literal, or one that does not fit in 64 bits: `0x`
+    InvalidNumber {
+        span: Span,
+    },
+
+    /// A token that is not allowed at this position: `ADD r0 r1`, or a line
+    /// that ends too early: `ADD r0,`
+    UnrecognizedToken {
+        token: Token,
+        span: Span,
+        expected: Vec<String>,
+    },
+
+    /// The input ended in the middle of an item: `ADD r0,`
+    UnrecognizedEof {
+        location: Location,
+        expected: Vec<String>,
+    },
+
+    /// A token left over after a complete item.
+    ExtraToken {
+        token: Token,
+        span: Span,
+    },
+
+    /// An immediate that does not fit in its instruction field: `LDI r0, #300`
+    ImmediateOutOfRange {
+        value: i64,
+        min: i64,
+        max: i64,
+        span: Span,
+    },
+
+    /// A data value that does not fit in its word: `.byte 300`
+    ValueOutOfRange {
+        value: i64,
+        min: i64,
+        max: i64,
+        span: Span,
+    },
+
+    /// An instruction in the data section, or data in the text section.
+    WrongSection {
+        /// The section the item belongs in.
+        section: Section,
+        span: Span,
+    },
+
+    /// A branch or jump to a label that is never defined.
+    UndefinedLabel {
+        label: String,
+        span: Span,
+    },
+
+    /// A label named on the command line that the program does not define:
+    /// `--halt done`
+    UnknownLabel {
+        label: String,
+
+        /// The option that names the label.
+        option: String,
+    },
+
+    /// An immediate that refers to a symbol that is never defined: `LDI r0, #SIZE`
+    UndefinedSymbol {
+        symbol: String,
+        span: Span,
+    },
+
+    /// A call of a function other than `lo` and `hi`: `#low(label)`
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+
+    /// A constant expression that divides by zero: `#1 / 0`
+    DivisionByZero {
+        span: Span,
+    },
+
+    /// A constant expression whose value does not fit in 64 bits.
+    ArithmeticOverflow {
+        span: Span,
+    },
+
+    /// A label that is defined more than once.
+    DuplicateLabel {
+        label: String,
+        span: Span,
+        previous: Span,
+    },
+
+    /// A name that is defined both as a label and as a symbol.
+    SymbolConflict {
+        symbol: String,
+        span: Span,
+        previous: Span,
+    },
+
+    /// An instruction or data word placed at an address that is already
+    /// used, e.g. after an `.org` that moves backwards.
+    Overlap {
+        address: u16,
+        span: Span,
+        previous: Span,
+    },
+
+    /// An instruction or data word placed past the last address of its
+    /// memory, `0xffff`.
+    AddressOverflow {
+        /// The section the item is in: `Text` for the instruction memory.
+        section: Section,
+        span: Span,
+    },
+
+    /// An `.include` of a file that is found neither next to the including
+    /// file nor in the include paths.
+    IncludeNotFound {
+        path: String,
+        span: Span,
+    },
+
+    /// An `.include` of a file that is already being read: `.include "a.asm"`
+    /// in `a.asm`.
+    IncludeCycle {
+        path: String,
+        span: Span,
+    },
+
+    /// A block directive without its counterpart: `.endm` without `.macro`,
+    /// `.if` without `.endif`, or `.else` without `.if`.
+    UnmatchedDirective {
+        directive: Token,
+        span: Span,
+    },
+
+    /// A macro that is defined more than once.
+    DuplicateMacro {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+
+    /// A macro call with the wrong number of arguments.
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+
+    /// A macro call nested too deeply, usually a macro that calls itself.
+    MacroRecursion {
+        name: String,
+        span: Span,
+    },
+
+    /// A branch or jump whose target is too far away for its displacement field.
+    BranchOutOfRange {
+        label: String,
+        distance: i64,
+        min: i64,
+        max: i64,
+        span: Span,
+    },
+
+    /// A word that encodes no instruction, met while running a program.
+    InvalidInstruction {
+        code: u16,
+        address: u16,
+    },
+
+    /// A read or write past the end of a memory while running a program:
+    /// `LD r0, (r1)` with `r1` beyond the data memory.
+    AddressOutOfRange {
+        /// The memory accessed: `Text` for the instruction memory.
+        section: Section,
+        address: u16,
+        size: usize,
+    },
+
+    /// A line of an execution trace that is in none of the trace formats.
+    InvalidTrace {
+        file: String,
+        line: usize,
+        reason: String,
+    },
+
+    Io(io::Error),
+
+    /// Several errors found in one run, in source order.
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    /// Combines `errors` into a single error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `errors` is empty.
+    pub fn from_errors(mut errors: Vec<Error>) -> Self {
+        assert!(!errors.is_empty(), "no errors to combine");
+
+        if errors.len() == 1 {
+            errors.pop().unwrap()
+        } else {
+            Error::Multiple(errors)
+        }
+    }
+
+    /// Splits the error into the individual errors it reports.
+    pub fn into_errors(self) -> Vec<Error> {
+        match self {
+            Error::Multiple(errors) => errors.into_iter().flat_map(Error::into_errors).collect(),
+            err => vec![err],
+        }
+    }
+
+    /// Returns the span of the source that caused the error, if any.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::InvalidToken { span }
+            | Error::UnterminatedComment { span }
+            | Error::InvalidNumber { span }
+            | Error::InvalidString { span }
+            | Error::UnrecognizedToken { span, .. }
+            | Error::ExtraToken { span, .. }
+            | Error::ImmediateOutOfRange { span, .. }
+            | Error::ValueOutOfRange { span, .. }
+            | Error::WrongSection { span, .. }
+            | Error::UndefinedLabel { span, .. }
+            | Error::UndefinedSymbol { span, .. }
+            | Error::UnknownFunction { span, .. }
+            | Error::DivisionByZero { span }
+            | Error::ArithmeticOverflow { span }
+            | Error::DuplicateLabel { span, .. }
+            | Error::SymbolConflict { span, .. }
+            | Error::Overlap { span, .. }
+            | Error::AddressOverflow { span, .. }
+            | Error::IncludeNotFound { span, .. }
+            | Error::IncludeCycle { span, .. }
+            | Error::UnmatchedDirective { span, .. }
+            | Error::DuplicateMacro { span, .. }
+            | Error::MacroArguments { span, .. }
+            | Error::MacroRecursion { span, .. }
+            | Error::BranchOutOfRange { span, .. } => Some(*span),
+            Error::UnrecognizedEof { location, .. } => Some(Span::new(*location, *location)),
+            Error::UnknownLabel { .. }
+            | Error::InvalidInstruction { .. }
+            | Error::AddressOutOfRange { .. }
+            | Error::InvalidTrace { .. }
+            | Error::Io(_)
+            | Error::Multiple(_) => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidToken { .. } => write!(f, "invalid token"),
+            Error::UnterminatedComment { .. } => write!(f, "unterminated block comment"),
+            Error::InvalidNumber { .. } => write!(f, "invalid number literal"),
+            Error::InvalidString { .. } => write!(f, "invalid string literal"),
+            Error::UnrecognizedToken {
+                token: Token::Newline,
+                ..
+            } => write!(f, "unexpected end of line"),
+            Error::UnrecognizedToken { token, .. } => write!(f, "unexpected token `{}`", token),
+            Error::UnrecognizedEof { .. } => write!(f, "unexpected end of file"),
+            Error::ExtraToken { token, .. } => write!(f, "extra token `{}`", token),
+            Error::ImmediateOutOfRange {
+                value, min, max, ..
+            } => write!(
+                f,
+                "immediate `{}` is out of range `{}..={}`",
+                value, min, max
+            ),
+            Error::ValueOutOfRange {
+                value, min, max, ..
+            } => write!(f, "value `{}` is out of range `{}..={}`", value, min, max),
+            Error::WrongSection {
+                section: Section::Text,
+                ..
+            } => write!(f, "instruction outside of the `.text` section"),
+            Error::WrongSection {
+                section: Section::Data,
+                ..
+            } => write!(f, "data outside of the `.data` section"),
+            Error::UndefinedLabel { label, .. } => write!(f, "undefined label `{}`", label),
+            Error::UnknownLabel { label, .. } => write!(f, "undefined label `{}`", label),
+            Error::UndefinedSymbol { symbol, .. } => write!(f, "undefined symbol `{}`", symbol),
+            Error::UnknownFunction { name, .. } => write!(f, "unknown function `{}`", name),
+            Error::DivisionByZero { .. } => write!(f, "division by zero"),
+            Error::ArithmeticOverflow { .. } => write!(f, "arithmetic overflow"),
+            Error::DuplicateLabel { label, .. } => {
+                write!(f, "label `{}` is defined multiple times", label)
+            }
+            Error::SymbolConflict { symbol, .. } => {
+                write!(f, "`{}` is defined both as a label and as a symbol", symbol)
+            }
+            Error::Overlap { address, .. } => {
+                write!(f, "address `{}` is already in use", address)
+            }
+            Error::AddressOverflow {
+                section: Section::Text,
+                ..
+            } => write!(f, "instruction past the end of the instruction memory"),
+            Error::AddressOverflow {
+                section: Section::Data,
+                ..
+            } => write!(f, "data past the end of the data memory"),
+            Error::IncludeNotFound { path, .. } => write!(f, "cannot find `{}` to include", path),
+            Error::IncludeCycle { path, .. } => write!(f, "`{}` is included recursively", path),
+            Error::UnmatchedDirective { directive, .. } => {
+                let counterpart = match directive {
+                    Token::Macro => ".endm",
+                    Token::Endm => ".macro",
+                    Token::If | Token::Ifdef | Token::Ifndef => ".endif",
+                    _ => ".if",
+                };
+                write!(f, "`{}` without a matching `{}`", directive, counterpart)
+            }
+            Error::DuplicateMacro { name, .. } => {
+                write!(f, "macro `{}` is defined multiple times", name)
+            }
+            Error::MacroArguments {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "macro `{}` takes {} argument{} but {} {} given",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            Error::MacroRecursion { name, .. } => {
+                write!(f, "recursion limit reached while expanding `{}`", name)
+            }
+            Error::BranchOutOfRange {
+                label,
+                distance,
+                min,
+                max,
+                ..
+            } => write!(
+                f,
+                "distance to `{}` is {}, out of range `{}..={}`",
+                label, distance, min, max
+            ),
+            Error::InvalidInstruction { code, address } => write!(
+                f,
+                "invalid instruction `{:016b}` at address `{}`",
+                code, address
+            ),
+            Error::AddressOutOfRange {
+                section,
+                address,
+                size,
+            } => write!(
+                f,
+                "address `{}` is outside of the {} memory of {} words",
+                address,
+                match section {
+                    Section::Text => "instruction",
+                    Section::Data => "data",
+                },
+                size
+            ),
+            Error::InvalidTrace { file, line, reason } => {
+                write!(f, "invalid trace at {}:{}: {}", file, line, reason)
+            }
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Multiple(errors) => write!(f, "{} errors", errors.len()),
+        }
+    }
+}
+
+/// A problem in the source that does not stop assembly.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Warning {
+    /// A label that no branch or jump refers to.
+    UnusedLabel { label: String, span: Span },
+}
+
+impl Warning {
+    pub fn span(&self) -> Span {
+        match self {
+            Warning::UnusedLabel { span, .. } => *span,
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnusedLabel { label, .. } => write!(f, "label `{}` is never used", label),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError<Location, Token, Error>> for Error {
+    fn from(err: ParseError<Location, Token, Error>) -> Self {
+        match err {
+            ParseError::InvalidToken { location } => Error::InvalidToken {
+                span: Span::new(location, location),
+            },
+            ParseError::UnrecognizedEOF { location, expected } => {
+                Error::UnrecognizedEof { location, expected }
+            }
+            ParseError::UnrecognizedToken {
+                token: (l, token, r),
+                expected,
+            } => Error::UnrecognizedToken {
+                token,
+                span: Span::new(l, r),
+                expected,
+            },
+            ParseError::ExtraToken {
+                token: (l, token, r),
+            } => Error::ExtraToken {
+                token,
+                span: Span::new(l, r),
+            },
+            ParseError::User { error } => error,
+        }
+    }
+}
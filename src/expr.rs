@@ -0,0 +1,316 @@
+use crate::{
+    error::{Error, Result},
+    span::Span,
+};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// A constant expression, evaluated when the program is assembled.
+///
+/// Constant operands are folded as the expression is built, so an expression
+/// without symbols is always a single `Num`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    /// A number: `16`, `-1`
+    Num(i64),
+
+    /// A symbol defined with `.equ` or `.set`, or a label: `SIZE`
+    Symbol(String),
+
+    /// A unary operation: `-x`, `~x`, `lo(x)`
+    Unary(UnaryOp, Box<Expr>),
+
+    /// A binary operation: `x + 1`
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn unary(op: UnaryOp, operand: Expr) -> Self {
+        match operand {
+            Expr::Num(value) => match op.apply(value) {
+                Some(result) => Expr::Num(result),
+                None => Expr::Unary(op, Box::new(operand)),
+            },
+            _ => Expr::Unary(op, Box::new(operand)),
+        }
+    }
+
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        match (&lhs, &rhs) {
+            (Expr::Num(l), Expr::Num(r)) => match op.apply(*l, *r) {
+                Some(result) => Expr::Num(result),
+                None => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+            },
+            _ => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    /// Replaces the symbols that `lookup` knows with their values, folding
+    /// the operations that become constant.
+    pub fn substitute<F>(&mut self, lookup: &F)
+    where
+        F: Fn(&str) -> Option<i64>,
+    {
+        *self = match std::mem::replace(self, Expr::Num(0)) {
+            Expr::Symbol(symbol) => match lookup(&symbol) {
+                Some(value) => Expr::Num(value),
+                None => Expr::Symbol(symbol),
+            },
+            Expr::Unary(op, mut operand) => {
+                operand.substitute(lookup);
+                Expr::unary(op, *operand)
+            }
+            Expr::Binary(op, mut lhs, mut rhs) => {
+                lhs.substitute(lookup);
+                rhs.substitute(lookup);
+                Expr::binary(op, *lhs, *rhs)
+            }
+            expr => expr,
+        };
+    }
+
+    /// Renames the symbols that `rename` gives a new name.
+    pub fn rename<F>(&mut self, rename: &F)
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        match self {
+            Expr::Num(_) => {}
+            Expr::Symbol(symbol) => {
+                if let Some(name) = rename(symbol) {
+                    *symbol = name;
+                }
+            }
+            Expr::Unary(_, operand) => operand.rename(rename),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.rename(rename);
+                rhs.rename(rename);
+            }
+        }
+    }
+
+    /// Evaluates the expression, looking symbols up with `lookup` and
+    /// reporting errors at `span`.
+    pub fn eval<F>(&self, lookup: &F, span: Span) -> Result<i64>
+    where
+        F: Fn(&str) -> Option<i64>,
+    {
+        match self {
+            Expr::Num(value) => Ok(*value),
+            Expr::Symbol(symbol) => lookup(symbol).ok_or_else(|| Error::UndefinedSymbol {
+                symbol: symbol.clone(),
+                span,
+            }),
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(lookup, span)?;
+                op.apply(value).ok_or(Error::ArithmeticOverflow { span })
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let l = lhs.eval(lookup, span)?;
+                let r = rhs.eval(lookup, span)?;
+                op.apply(l, r).ok_or(match op {
+                    BinaryOp::Div | BinaryOp::Rem if r == 0 => Error::DivisionByZero { span },
+                    _ => Error::ArithmeticOverflow { span },
+                })
+            }
+        }
+    }
+
+    /// Returns the symbols the expression refers to.
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Num(_) => Vec::new(),
+            Expr::Symbol(symbol) => vec![symbol.as_str()],
+            Expr::Unary(_, operand) => operand.symbols(),
+            Expr::Binary(_, lhs, rhs) => {
+                let mut symbols = lhs.symbols();
+                symbols.extend(rhs.symbols());
+                symbols
+            }
+        }
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(value: i64) -> Self {
+        Expr::Num(value)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Num(value) => write!(f, "{}", value),
+            Expr::Symbol(symbol) => write!(f, "{}", symbol),
+            Expr::Unary(UnaryOp::Neg, operand) => write!(f, "-{}", operand),
+            Expr::Unary(UnaryOp::Not, operand) => write!(f, "~{}", operand),
+            Expr::Unary(UnaryOp::Lo, operand) => write!(f, "lo({})", operand),
+            Expr::Unary(UnaryOp::Hi, operand) => write!(f, "hi({})", operand),
+            Expr::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op, rhs),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+
+    /// The low byte of a 16-bit value.
+    Lo,
+
+    /// The high byte of a 16-bit value.
+    Hi,
+}
+
+impl UnaryOp {
+    /// Applies the operation, returning `None` on overflow.
+    pub fn apply(&self, value: i64) -> Option<i64> {
+        match self {
+            UnaryOp::Neg => value.checked_neg(),
+            UnaryOp::Not => Some(!value),
+            UnaryOp::Lo => Some(value & 0xff),
+            UnaryOp::Hi => Some((value >> 8) & 0xff),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+}
+
+impl BinaryOp {
+    /// Applies the operation, returning `None` on overflow, division by zero
+    /// or a shift by more than 63 bits.
+    pub fn apply(&self, l: i64, r: i64) -> Option<i64> {
+        let shift = u32::try_from(r).ok();
+
+        match self {
+            BinaryOp::Add => l.checked_add(r),
+            BinaryOp::Sub => l.checked_sub(r),
+            BinaryOp::Mul => l.checked_mul(r),
+            BinaryOp::Div => l.checked_div(r),
+            BinaryOp::Rem => l.checked_rem(r),
+            BinaryOp::Shl => shift.and_then(|r| l.checked_shl(r)),
+            BinaryOp::Shr => shift.and_then(|r| l.checked_shr(r)),
+            BinaryOp::And => Some(l & r),
+            BinaryOp::Or => Some(l | r),
+            BinaryOp::Xor => Some(l ^ r),
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryOp::Add => write!(f, "+"),
+            BinaryOp::Sub => write!(f, "-"),
+            BinaryOp::Mul => write!(f, "*"),
+            BinaryOp::Div => write!(f, "/"),
+            BinaryOp::Rem => write!(f, "%"),
+            BinaryOp::Shl => write!(f, "<<"),
+            BinaryOp::Shr => write!(f, ">>"),
+            BinaryOp::And => write!(f, "&"),
+            BinaryOp::Or => write!(f, "|"),
+            BinaryOp::Xor => write!(f, "^"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn symbol(name: &str) -> Expr {
+        Expr::Symbol(name.to_string())
+    }
+
+    #[test]
+    fn fold() {
+        let expr = Expr::binary(
+            BinaryOp::Add,
+            Expr::Num(1),
+            Expr::binary(BinaryOp::Mul, Expr::Num(2), Expr::Num(3)),
+        );
+        assert_eq!(expr, Expr::Num(7));
+
+        assert_eq!(Expr::unary(UnaryOp::Neg, Expr::Num(1)), Expr::Num(-1));
+        assert_eq!(Expr::unary(UnaryOp::Not, Expr::Num(0)), Expr::Num(-1));
+        assert_eq!(Expr::unary(UnaryOp::Lo, Expr::Num(0x1234)), Expr::Num(0x34));
+        assert_eq!(Expr::unary(UnaryOp::Hi, Expr::Num(0x1234)), Expr::Num(0x12));
+
+        // Operations that fail are kept so that they can be reported.
+        assert_eq!(
+            Expr::binary(BinaryOp::Div, Expr::Num(1), Expr::Num(0)),
+            Expr::Binary(
+                BinaryOp::Div,
+                Box::new(Expr::Num(1)),
+                Box::new(Expr::Num(0))
+            )
+        );
+    }
+
+    #[test]
+    fn substitute() {
+        let mut symbols = HashMap::new();
+        symbols.insert("SIZE".to_string(), 4);
+        let lookup = |name: &str| symbols.get(name).copied();
+
+        let mut expr = Expr::binary(BinaryOp::Shl, symbol("SIZE"), Expr::Num(1));
+        expr.substitute(&lookup);
+        assert_eq!(expr, Expr::Num(8));
+
+        let mut expr = Expr::binary(BinaryOp::Add, symbol("OTHER"), symbol("SIZE"));
+        expr.substitute(&lookup);
+        assert_eq!(
+            expr,
+            Expr::Binary(
+                BinaryOp::Add,
+                Box::new(symbol("OTHER")),
+                Box::new(Expr::Num(4))
+            )
+        );
+        assert_eq!(expr.symbols(), vec!["OTHER"]);
+    }
+
+    #[test]
+    fn eval() {
+        let mut symbols = HashMap::new();
+        symbols.insert("SIZE".to_string(), 4);
+        let lookup = |name: &str| symbols.get(name).copied();
+
+        let span = Span::default();
+        assert_eq!(Expr::Num(-1).eval(&lookup, span).unwrap(), -1);
+        assert_eq!(symbol("SIZE").eval(&lookup, span).unwrap(), 4);
+
+        match symbol("OTHER").eval(&lookup, span) {
+            Err(Error::UndefinedSymbol { symbol, .. }) => assert_eq!(symbol, "OTHER"),
+            result => panic!("expected an undefined symbol error, got {:?}", result),
+        }
+
+        let expr = Expr::binary(BinaryOp::Rem, Expr::Num(1), Expr::Num(0));
+        assert!(matches!(
+            expr.eval(&lookup, span),
+            Err(Error::DivisionByZero { .. })
+        ));
+
+        let expr = Expr::binary(BinaryOp::Shl, Expr::Num(1), Expr::Num(64));
+        assert!(matches!(
+            expr.eval(&lookup, span),
+            Err(Error::ArithmeticOverflow { .. })
+        ));
+    }
+}
@@ -1,11 +1,55 @@
-use crate::token::*;
-use core::panic;
+use crate::{
+    error::{Error, Result},
+    span::{FileId, Location, Span, Spanned},
+    token::*,
+};
 use std::str::Chars;
 
 #[derive(Debug)]
 pub struct Lexer<'input> {
     chars: Chars<'input>,
     ch: Option<char>,
+
+    /// The location of `ch`.
+    location: Location,
+
+    /// The line of the last token read, used to tell a `#` comment at the
+    /// start of a line from the `#` of an immediate.
+    token_line: Option<u32>,
+
+    /// The comments skipped so far.
+    comments: Vec<Spanned<String>>,
+
+    /// Whether a token has been read since the last newline, so that an
+    /// unterminated last line still ends with a `Newline` token.
+    line_open: bool,
+
+    /// Where the next token falls in its statement.
+    position: Position,
+
+    strict_case: bool,
+}
+
+/// The place of a token within a statement. Pseudo-instruction names are
+/// keywords only where a mnemonic may stand, so that they remain free for
+/// labels and symbols: `:call`, `JMP ret`. Likewise, keywords written in
+/// another case are only read where a mnemonic or a register may stand.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Position {
+    /// At the start of a statement, possibly after labels.
+    Start,
+
+    /// After the `:` of a label.
+    Label,
+
+    /// Among the operands of a branch or `LA`, before the label.
+    Branch,
+
+    /// At the label operand of a jump or branch.
+    Target,
+
+    /// Inside a statement.
+    Rest,
 }
 
 impl<'input> Lexer<'input> {
@@ -13,62 +57,290 @@ impl<'input> Lexer<'input> {
         let mut lexer = Lexer {
             chars: input.chars(),
             ch: None,
+            location: Location::new(0, 1, 1),
+            token_line: None,
+            comments: Vec::new(),
+            line_open: false,
+            position: Position::Start,
+            strict_case: false,
         };
         lexer.read_char();
 
         lexer
     }
 
+    /// When `strict` is set, mnemonics are only recognized in uppercase and
+    /// registers in lowercase, e.g. `ADD r0, r1`. Otherwise keywords are
+    /// case-insensitive. Labels are always case-sensitive.
+    pub fn strict_case(mut self, strict: bool) -> Self {
+        self.strict_case = strict;
+        self
+    }
+
+    /// Sets the file that the locations of the tokens refer to.
+    pub fn file(mut self, file: FileId) -> Self {
+        self.location.file = file;
+        self
+    }
+
+    /// Returns the comments skipped so far, including their delimiters.
+    pub fn comments(&self) -> &[Spanned<String>] {
+        &self.comments
+    }
+
+    pub fn into_comments(self) -> Vec<Spanned<String>> {
+        self.comments
+    }
+
     fn read_char(&mut self) {
+        if let Some(ch) = self.ch {
+            self.location.offset += ch.len_utf8() as u32;
+            if ch == '\n' {
+                self.location.line += 1;
+                self.location.column = 1;
+            } else {
+                self.location.column += 1;
+            }
+        }
+
         self.ch = self.chars.next();
     }
 
-    fn skip_whitespace(&mut self) {
+    fn peek_char(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
+    /// Skips whitespace other than newlines and comments: `; ...`, `// ...`,
+    /// `/* ... */`, and `# ...` when `#` is the first token on its line and
+    /// followed by a space.
+    fn skip_trivia(&mut self) -> Result<()> {
+        loop {
+            while let Some(c) = self.ch {
+                if !c.is_whitespace() || c == '\n' {
+                    break;
+                }
+                self.read_char();
+            }
+
+            // `#1` is still an immediate; only `# ...` and `##...` start a comment.
+            let at_line_start = self.token_line != Some(self.location.line);
+            let sharp_comment =
+                |next: Option<char>| matches!(next, Some(c) if c.is_whitespace() || c == '#');
+
+            match (self.ch, self.peek_char()) {
+                (Some(';'), _) | (Some('/'), Some('/')) => self.read_line_comment(),
+                (Some('#'), next) if at_line_start && sharp_comment(next) => {
+                    self.read_line_comment()
+                }
+                (Some('/'), Some('*')) => self.read_block_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_line_comment(&mut self) {
+        let start = self.location;
+
+        let mut text = String::new();
         while let Some(c) = self.ch {
-            if !c.is_whitespace() {
+            if c == '\n' {
                 break;
             }
+            text.push(c);
             self.read_char();
         }
+
+        let span = self.span_from(start);
+        self.comments
+            .push(Spanned::new(text.trim_end().to_string(), span));
     }
 
-    fn read_number(&mut self) -> Token {
-        match self.ch {
-            Some(ch) => {
-                if !ch.is_digit(10) {
-                    panic!("A non-numeric value was entered")
+    fn read_block_comment(&mut self) -> Result<()> {
+        let start = self.location;
+
+        // Skip the opening `/*` so that `/*/` is not taken as a whole comment.
+        let mut text = String::from("/*");
+        self.read_char();
+        self.read_char();
+
+        loop {
+            match self.ch {
+                Some('*') if self.peek_char() == Some('/') => {
+                    text.push_str("*/");
+                    self.read_char();
+                    self.read_char();
+                    break;
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.read_char();
+                }
+                None => {
+                    return Err(Error::UnterminatedComment {
+                        span: self.span_from(start),
+                    })
                 }
             }
-            None => panic!("Entered string has already reached the end."),
         }
 
-        let mut digits = String::from(self.ch.unwrap());
-        loop {
+        let span = self.span_from(start);
+        self.comments.push(Spanned::new(text, span));
+        Ok(())
+    }
+
+    /// Returns the span from `start` to the current location.
+    fn span_from(&self, start: Location) -> Span {
+        Span::new(start, self.location)
+    }
+
+    fn invalid_token(&mut self) -> Error {
+        let start = self.location;
+        self.read_char();
+
+        Error::InvalidToken {
+            span: self.span_from(start),
+        }
+    }
+
+    /// Reads a number literal: `16`, `0x10`, `0b0001_0000`, `0o20`
+    fn read_number(&mut self) -> Result<Token> {
+        let start = self.location;
+
+        match self.ch {
+            Some(ch) if ch.is_ascii_digit() => {}
+            _ => return Err(self.invalid_token()),
+        }
+
+        let mut literal = String::new();
+        while let Some(c) = self.ch {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            if c != '_' {
+                literal.push(c);
+            }
+            self.read_char();
+        }
+
+        // A reference to an anonymous label: `1b`, `1f`
+        if let Some(label) = literal
+            .strip_suffix('b')
+            .or_else(|| literal.strip_suffix('f'))
+        {
+            if !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit()) {
+                return Ok(Token::Ident(literal));
+            }
+        }
+
+        let (digits, radix) = match literal.get(..2) {
+            Some("0x") | Some("0X") => (&literal[2..], 16),
+            Some("0b") | Some("0B") => (&literal[2..], 2),
+            Some("0o") | Some("0O") => (&literal[2..], 8),
+            _ => (literal.as_str(), 10),
+        };
+
+        i64::from_str_radix(digits, radix)
+            .map(Token::Num)
+            .map_err(|_| Error::InvalidNumber {
+                span: self.span_from(start),
+            })
+    }
+
+    /// Reads a character literal as its code point: `'A'`, `'\n'`
+    fn read_char_literal(&mut self) -> Result<Token> {
+        let start = self.location;
+        self.read_char();
+
+        let value = match self.ch {
+            Some('\\') => self.read_escape(),
+            Some('\'') | Some('\n') | None => None,
+            ch => ch,
+        };
+
+        if value.is_some() {
             self.read_char();
-            if let Some(c) = self.ch {
-                if c.is_digit(10) {
-                    digits.push(c);
-                    continue;
+        }
+
+        match (value, self.ch) {
+            (Some(value), Some('\'')) => {
+                self.read_char();
+                Ok(Token::Num(value as i64))
+            }
+            _ => Err(Error::InvalidNumber {
+                span: self.span_from(start),
+            }),
+        }
+    }
+
+    /// Reads a string literal: `"abc"`, `"line\n"`
+    ///
+    /// An invalid string is skipped up to its closing quote or the end of the
+    /// line.
+    fn read_string_literal(&mut self) -> Result<Token> {
+        let start = self.location;
+        self.read_char();
+
+        let mut text = String::new();
+        let mut valid = true;
+        loop {
+            match self.ch {
+                Some('"') => {
+                    self.read_char();
+                    break;
                 }
+                Some('\n') | None => {
+                    valid = false;
+                    break;
+                }
+                Some('\\') => match self.read_escape() {
+                    Some(c) => text.push(c),
+                    None if matches!(self.ch, Some('\n') | None) => {
+                        valid = false;
+                        break;
+                    }
+                    None => valid = false,
+                },
+                Some(c) => text.push(c),
             }
-            break;
+            self.read_char();
+        }
+
+        if valid {
+            Ok(Token::Str(text))
+        } else {
+            Err(Error::InvalidString {
+                span: self.span_from(start),
+            })
         }
+    }
 
-        Token::Num(digits)
+    /// Reads the character after a `\` and returns the character the escape
+    /// stands for.
+    fn read_escape(&mut self) -> Option<char> {
+        self.read_char();
+        match self.ch {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('0') => Some('\0'),
+            Some('\\') => Some('\\'),
+            Some('\'') => Some('\''),
+            Some('"') => Some('"'),
+            _ => None,
+        }
     }
 
-    fn read_str(&mut self) -> String {
+    /// Reads a label, a keyword, or a directive such as `.equ`.
+    fn read_str(&mut self) -> Result<String> {
         let is_letter = |c: char| c.is_ascii_alphanumeric() || c == '_';
 
-        let ch = self
-            .ch
-            .expect("Entered string has already reached the end.");
-
-        if !is_letter(ch) {
-            panic!("A non-alphanumeric character was enterd.");
+        match self.ch {
+            Some(ch) if is_letter(ch) => {}
+            Some('.') if self.peek_char().is_some_and(is_letter) => {}
+            _ => return Err(self.invalid_token()),
         }
 
-        let mut literal = String::from(ch);
+        let mut literal = String::from(self.ch.unwrap());
         loop {
             self.read_char();
             match self.ch {
@@ -83,65 +355,97 @@ impl<'input> Lexer<'input> {
             }
         }
 
-        literal
+        Ok(literal)
+    }
+
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        let token = self.read_token();
+        self.position = match (self.position, &token) {
+            (_, Ok(Some(Token::Newline))) => Position::Start,
+            (Position::Start, Ok(Some(Token::Colon))) => Position::Label,
+            (Position::Label, Ok(Some(_))) => Position::Start,
+            (Position::Start, Ok(Some(Token::JMP | Token::JAL | Token::B | Token::CALL))) => {
+                Position::Target
+            }
+            (
+                Position::Start,
+                Ok(Some(Token::BEZ | Token::BNZ | Token::BPL | Token::BMI | Token::LA)),
+            ) => Position::Branch,
+            (Position::Branch, Ok(Some(Token::Comma))) => Position::Target,
+            (Position::Branch, Ok(Some(_))) => Position::Branch,
+            (position, Ok(None)) => position,
+            _ => Position::Rest,
+        };
+
+        token
     }
 
-    pub fn next_token(&mut self) -> Option<Token> {
-        self.skip_whitespace();
+    /// Reads a word written in another case than its keyword, where it can
+    /// only be a mnemonic or a register. Elsewhere it stays a label or a
+    /// symbol: `:add`, `JMP add`.
+    fn keyword_in_other_case(&self, word: &str) -> Option<Token> {
+        match self.position {
+            Position::Start => Token::keyword(&word.to_ascii_uppercase())
+                .or_else(|| Token::keyword(&word.to_ascii_lowercase())),
+            Position::Branch | Position::Rest => {
+                Token::keyword(&word.to_ascii_lowercase()).filter(Token::is_register)
+            }
+            Position::Label | Position::Target => None,
+        }
+    }
+
+    fn read_token(&mut self) -> Result<Option<Token>> {
+        self.skip_trivia()?;
+        if self.ch.is_some() {
+            self.token_line = Some(self.location.line);
+        }
 
         let token = match self.ch {
             Some(ch) => Some(match ch {
                 '#' => Token::Sharp,
                 '-' => Token::Minus,
+                '+' => Token::Plus,
+                '*' => Token::Star,
+                '/' => Token::Slash,
+                '%' => Token::Percent,
+                '&' => Token::Ampersand,
+                '|' => Token::Pipe,
+                '^' => Token::Caret,
+                '~' => Token::Tilde,
+                '<' | '>' if self.peek_char() == Some(ch) => {
+                    self.read_char();
+                    if ch == '<' {
+                        Token::Shl
+                    } else {
+                        Token::Shr
+                    }
+                }
                 ',' => Token::Comma,
                 ':' => Token::Colon,
                 '(' => Token::OpenParen,
                 ')' => Token::CloseParen,
+                '\n' => Token::Newline,
 
-                '0'..='9' => return Some(self.read_number()),
+                '0'..='9' => return self.read_number().map(Some),
+                '\'' => return self.read_char_literal().map(Some),
+                '"' => return self.read_string_literal().map(Some),
 
                 _ => {
-                    let token = match self.read_str().as_str() {
-                        "NOP" => Token::NOP,
-                        "MV" => Token::MV,
-                        "AND" => Token::AND,
-                        "OR" => Token::OR,
-                        "SL" => Token::SL,
-                        "SR" => Token::SR,
-                        "ADD" => Token::ADD,
-                        "SUB" => Token::SUB,
-                        "ST" => Token::ST,
-                        "LD" => Token::LD,
-
-                        "LDI" => Token::LDI,
-                        "LDIU" => Token::LDIU,
-                        "ADDI" => Token::ADDI,
-                        "ADDIU" => Token::ADDIU,
-                        "LDHI" => Token::LDHI,
-
-                        "BEZ" => Token::BEZ,
-                        "BNZ" => Token::BNZ,
-                        "BPL" => Token::BPL,
-                        "BMI" => Token::BMI,
-
-                        "JMP" => Token::JMP,
-                        "JAL" => Token::JAL,
-                        "JR" => Token::JR,
-                        "JALR" => Token::JALR,
-
-                        "r0" => Token::R0,
-                        "r1" => Token::R1,
-                        "r2" => Token::R2,
-                        "r3" => Token::R3,
-                        "r4" => Token::R4,
-                        "r5" => Token::R5,
-                        "r6" => Token::R6,
-                        "r7" => Token::R7,
-
-                        ident => Token::Ident(ident.to_string()),
+                    let word = self.read_str()?;
+                    let keyword = match Token::keyword(&word) {
+                        None if !self.strict_case => self.keyword_in_other_case(&word),
+                        keyword => keyword,
+                    };
+                    let token = match keyword {
+                        Some(keyword)
+                            if !keyword.is_pseudo() || self.position == Position::Start =>
+                        {
+                            keyword
+                        }
+                        _ => Token::Ident(word),
                     };
 
-                    return Some(token);
+                    return Ok(Some(token));
                 }
             }),
             None => None,
@@ -149,17 +453,33 @@ impl<'input> Lexer<'input> {
 
         self.read_char();
 
-        token
+        Ok(token)
     }
 }
 
 impl<'input> Iterator for Lexer<'input> {
-    type Item = ((), Token, ());
+    type Item = Result<(Location, Token, Location)>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.skip_trivia() {
+            return Some(Err(err));
+        }
+
+        let start = self.location;
         match self.next_token() {
-            Some(token) => Some(((), token, ())),
-            None => None,
+            Ok(Some(token)) => {
+                self.line_open = token != Token::Newline;
+                Some(Ok((start, token, self.location)))
+            }
+            Ok(None) if self.line_open => {
+                self.line_open = false;
+                Some(Ok((start, Token::Newline, start)))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                self.line_open = true;
+                Some(Err(err))
+            }
         }
     }
 }
@@ -175,7 +495,7 @@ mod tests {
             let mut lexer = Lexer::new($input);
             let mut tokens: Vec<Token> = Vec::new();
 
-            while let Some(token) = lexer.next_token() {
+            while let Some(token) = lexer.next_token().unwrap() {
                 tokens.push(token);
             }
 
@@ -185,7 +505,7 @@ mod tests {
 
     macro_rules! token_num {
         ($value: expr) => {
-            Token::Num($value.to_string())
+            Token::Num($value)
         };
     }
 
@@ -204,6 +524,75 @@ mod tests {
 
         test_lexer!("#-1", vec![Token::Sharp, Token::Minus, token_num!(1)]);
         test_lexer!("#-16", vec![Token::Sharp, Token::Minus, token_num!(16)]);
+
+        test_lexer!("0x1f", vec![token_num!(0x1f)]);
+        test_lexer!("0XFF", vec![token_num!(0xff)]);
+        test_lexer!("0b1010", vec![token_num!(0b1010)]);
+        test_lexer!("0b0000_1111", vec![token_num!(0b0000_1111)]);
+        test_lexer!("0o17", vec![token_num!(0o17)]);
+        test_lexer!("#-0x80", vec![Token::Sharp, Token::Minus, token_num!(0x80)]);
+    }
+
+    #[test]
+    fn char_literal() {
+        test_lexer!("'A'", vec![token_num!(65)]);
+        test_lexer!("'0'", vec![token_num!(48)]);
+        test_lexer!("' '", vec![token_num!(32)]);
+        test_lexer!("'\\n'", vec![token_num!(10)]);
+        test_lexer!("'\\''", vec![token_num!(39)]);
+        test_lexer!("#'a'", vec![Token::Sharp, token_num!(97)]);
+    }
+
+    #[test]
+    fn string_literal() {
+        test_lexer!("\"abc\"", vec![Token::Str("abc".to_string())]);
+        test_lexer!("\"\"", vec![Token::Str(String::new())]);
+        test_lexer!(
+            "\"a\\n\\\"b\\\"\"",
+            vec![Token::Str("a\n\"b\"".to_string())]
+        );
+        test_lexer!(
+            "\"; not a comment\"",
+            vec![Token::Str("; not a comment".to_string())]
+        );
+
+        for input in &["\"abc", "\"a\\qb\"", "\"abc\\"] {
+            let mut lexer = Lexer::new(input);
+            match lexer.next_token() {
+                Err(Error::InvalidString { .. }) => {}
+                result => panic!(
+                    "expected an invalid string error for {}, got {:?}",
+                    input, result
+                ),
+            }
+        }
+
+        // The newline after an unterminated string is still a token.
+        let mut lexer = Lexer::new("\"abc\nNOP");
+        assert!(lexer.next_token().is_err());
+        assert_eq!(lexer.next_token().unwrap(), Some(Token::Newline));
+    }
+
+    #[test]
+    fn invalid_number() {
+        for input in &[
+            "0x",
+            "0b102",
+            "12ab",
+            "99999999999999999999",
+            "''",
+            "'ab'",
+            "'a",
+        ] {
+            let mut lexer = Lexer::new(input);
+            match lexer.next_token() {
+                Err(Error::InvalidNumber { .. }) => {}
+                result => panic!(
+                    "expected an invalid number error for {}, got {:?}",
+                    input, result
+                ),
+            }
+        }
     }
 
     #[test]
@@ -214,6 +603,30 @@ mod tests {
         test_lexer!(":", vec![Token::Colon]);
         test_lexer!("(", vec![Token::OpenParen]);
         test_lexer!(")", vec![Token::CloseParen]);
+        test_lexer!(
+            "+ * / % << >> & | ^ ~",
+            vec![
+                Token::Plus,
+                Token::Star,
+                Token::Slash,
+                Token::Percent,
+                Token::Shl,
+                Token::Shr,
+                Token::Ampersand,
+                Token::Pipe,
+                Token::Caret,
+                Token::Tilde
+            ]
+        );
+        test_lexer!(
+            "lo(x)",
+            vec![
+                Token::Ident("lo".to_string()),
+                Token::OpenParen,
+                Token::Ident("x".to_string()),
+                Token::CloseParen
+            ]
+        );
     }
 
     #[test]
@@ -227,6 +640,16 @@ mod tests {
             ":label1",
             vec![Token::Colon, Token::Ident("label1".to_string())]
         );
+        test_lexer!(
+            ":.loop",
+            vec![Token::Colon, Token::Ident(".loop".to_string())]
+        );
+
+        // References to anonymous labels.
+        test_lexer!("1b", vec![Token::Ident("1b".to_string())]);
+        test_lexer!("12f", vec![Token::Ident("12f".to_string())]);
+        test_lexer!("0b", vec![Token::Ident("0b".to_string())]);
+        test_lexer!("0b1", vec![Token::Num(1)]);
     }
 
     #[test]
@@ -263,6 +686,143 @@ mod tests {
         // JR instruction
         test_lexer!("JR", vec![Token::JR]);
         test_lexer!("JALR", vec![Token::JALR]);
+
+        // Pseudo-instruction
+        test_lexer!("LI", vec![Token::LI]);
+        test_lexer!("LA", vec![Token::LA]);
+        test_lexer!("CLR", vec![Token::CLR]);
+        test_lexer!("NOT", vec![Token::NOT]);
+        test_lexer!("NEG", vec![Token::NEG]);
+        test_lexer!("INC", vec![Token::INC]);
+        test_lexer!("DEC", vec![Token::DEC]);
+        test_lexer!("B", vec![Token::B]);
+        test_lexer!("CALL", vec![Token::CALL]);
+        test_lexer!("RET", vec![Token::RET]);
+        test_lexer!("PUSH", vec![Token::PUSH]);
+        test_lexer!("POP", vec![Token::POP]);
+    }
+
+    #[test]
+    fn keyword_pseudo_position() {
+        let ident = |name: &str| Token::Ident(name.to_string());
+
+        // Pseudo-instruction names are keywords only in place of a mnemonic.
+        test_lexer!(
+            ":call :b call b\nJMP ret\n.equ push, 1",
+            vec![
+                Token::Colon,
+                ident("call"),
+                Token::Colon,
+                ident("b"),
+                Token::CALL,
+                ident("b"),
+                Token::Newline,
+                Token::JMP,
+                ident("ret"),
+                Token::Newline,
+                Token::Equ,
+                ident("push"),
+                Token::Comma,
+                Token::Num(1)
+            ]
+        );
+
+        // The other mnemonics are keywords everywhere, but only in their own
+        // case.
+        test_lexer!(":ADD", vec![Token::Colon, Token::ADD]);
+        test_lexer!(":add", vec![Token::Colon, ident("add")]);
+    }
+
+    #[test]
+    fn keyword_case() {
+        test_lexer!("add", vec![Token::ADD]);
+        test_lexer!("Addiu", vec![Token::ADDIU]);
+        test_lexer!("jalr", vec![Token::JALR]);
+        test_lexer!("R0", vec![Token::R0]);
+        test_lexer!("R7", vec![Token::R7]);
+        test_lexer!(
+            "add R0, r1",
+            vec![Token::ADD, Token::R0, Token::Comma, Token::R1]
+        );
+
+        // Labels keep their case.
+        test_lexer!("Loop", vec![Token::Ident("Loop".to_string())]);
+
+        // A label or symbol is not read as a keyword in another case.
+        let ident = |name: &str| Token::Ident(name.to_string());
+        test_lexer!(
+            ":add JMP add\nbez R0, sub\nLDI r0, #and",
+            vec![
+                Token::Colon,
+                ident("add"),
+                Token::JMP,
+                ident("add"),
+                Token::Newline,
+                Token::BEZ,
+                Token::R0,
+                Token::Comma,
+                ident("sub"),
+                Token::Newline,
+                Token::LDI,
+                Token::R0,
+                Token::Comma,
+                Token::Sharp,
+                ident("and")
+            ]
+        );
+    }
+
+    #[test]
+    fn keyword_strict_case() {
+        let tokens = |input| -> Vec<Token> {
+            Lexer::new(input)
+                .strict_case(true)
+                .map(|t| t.unwrap().1)
+                .collect()
+        };
+
+        assert_eq!(
+            tokens("ADD r0"),
+            vec![Token::ADD, Token::R0, Token::Newline]
+        );
+        assert_eq!(
+            tokens("add R0"),
+            vec![
+                Token::Ident("add".to_string()),
+                Token::Ident("R0".to_string()),
+                Token::Newline
+            ]
+        );
+    }
+
+    #[test]
+    fn keyword_directive() {
+        test_lexer!(".equ", vec![Token::Equ]);
+        test_lexer!(".set", vec![Token::Set]);
+        test_lexer!(".EQU", vec![Token::Equ]);
+        test_lexer!(".org", vec![Token::Org]);
+        test_lexer!(".align", vec![Token::Align]);
+        test_lexer!(".include", vec![Token::Include]);
+        test_lexer!(".macro", vec![Token::Macro]);
+        test_lexer!(".endm", vec![Token::Endm]);
+        test_lexer!(".ifndef", vec![Token::Ifndef]);
+        test_lexer!(".endif", vec![Token::Endif]);
+        test_lexer!(".other", vec![Token::Ident(".other".to_string())]);
+        test_lexer!(
+            ".equ IO, 2",
+            vec![
+                Token::Equ,
+                Token::Ident("IO".to_string()),
+                Token::Comma,
+                token_num!(2)
+            ]
+        );
+
+        let mut lexer = Lexer::new(". equ");
+        assert!(matches!(
+            lexer.next_token(),
+            Err(Error::InvalidToken { .. })
+        ));
     }
 
     #[test]
@@ -277,6 +837,123 @@ mod tests {
         test_lexer!("r7", vec![Token::R7]);
     }
 
+    #[test]
+    fn invalid_token() {
+        let mut lexer = Lexer::new("ADD @ r0");
+        assert_eq!(lexer.next_token().unwrap(), Some(Token::ADD));
+
+        match lexer.next_token() {
+            Err(Error::InvalidToken { span }) => assert_eq!(
+                span,
+                Span::new(Location::new(4, 1, 5), Location::new(5, 1, 6))
+            ),
+            result => panic!("expected an invalid token error, got {:?}", result),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Token::R0));
+    }
+
+    #[test]
+    fn comment() {
+        test_lexer!("; comment", Vec::<Token>::new());
+        test_lexer!("NOP ; comment", vec![Token::NOP]);
+        test_lexer!("NOP // comment", vec![Token::NOP]);
+        test_lexer!("NOP /* comment */", vec![Token::NOP]);
+        test_lexer!("# comment\nNOP", vec![Token::Newline, Token::NOP]);
+        test_lexer!("  # comment\nNOP", vec![Token::Newline, Token::NOP]);
+        test_lexer!("## comment\nNOP", vec![Token::Newline, Token::NOP]);
+        test_lexer!("#\nNOP", vec![Token::Newline, Token::NOP]);
+        test_lexer!(
+            "MV r0, r1 /* a\nb */ SL r0",
+            vec![
+                Token::MV,
+                Token::R0,
+                Token::Comma,
+                Token::R1,
+                Token::SL,
+                Token::R0
+            ]
+        );
+        test_lexer!(
+            "NOP\n# comment\nLDI r0, #1 ; #2",
+            vec![
+                Token::NOP,
+                Token::Newline,
+                Token::Newline,
+                Token::LDI,
+                Token::R0,
+                Token::Comma,
+                Token::Sharp,
+                token_num!(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn comment_trivia() {
+        let mut lexer = Lexer::new("; first\nNOP // second\n/* third */ NOP");
+        let tokens: Vec<Token> = lexer.by_ref().map(|t| t.unwrap().1).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Newline,
+                Token::NOP,
+                Token::Newline,
+                Token::NOP,
+                Token::Newline
+            ]
+        );
+
+        let comments: Vec<(&str, u32)> = lexer
+            .comments()
+            .iter()
+            .map(|c| (c.node.as_str(), c.span.start.line))
+            .collect();
+        assert_eq!(
+            comments,
+            vec![("; first", 1), ("// second", 2), ("/* third */", 3)]
+        );
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        let mut lexer = Lexer::new("NOP /* comment");
+        assert_eq!(lexer.next_token().unwrap(), Some(Token::NOP));
+
+        match lexer.next_token() {
+            Err(Error::UnterminatedComment { span }) => {
+                assert_eq!(span.start, Location::new(4, 1, 5))
+            }
+            result => panic!("expected an unterminated comment error, got {:?}", result),
+        }
+        assert_eq!(lexer.next_token().unwrap(), None);
+    }
+
+    #[test]
+    fn location() {
+        let lexer = Lexer::new("LDI r0, #1\n:end\n  JMP end");
+        let locations: Vec<(Location, Location)> =
+            lexer.map(|t| t.unwrap()).map(|(l, _, r)| (l, r)).collect();
+
+        assert_eq!(
+            locations,
+            vec![
+                (Location::new(0, 1, 1), Location::new(3, 1, 4)),
+                (Location::new(4, 1, 5), Location::new(6, 1, 7)),
+                (Location::new(6, 1, 7), Location::new(7, 1, 8)),
+                (Location::new(8, 1, 9), Location::new(9, 1, 10)),
+                (Location::new(9, 1, 10), Location::new(10, 1, 11)),
+                (Location::new(10, 1, 11), Location::new(11, 2, 1)),
+                (Location::new(11, 2, 1), Location::new(12, 2, 2)),
+                (Location::new(12, 2, 2), Location::new(15, 2, 5)),
+                (Location::new(15, 2, 5), Location::new(16, 3, 1)),
+                (Location::new(18, 3, 3), Location::new(21, 3, 6)),
+                (Location::new(22, 3, 7), Location::new(25, 3, 10)),
+                (Location::new(25, 3, 10), Location::new(25, 3, 10)),
+            ]
+        );
+    }
+
     #[test]
     fn instruction() {
         test_lexer!("NOP", vec![Token::NOP]);
@@ -1,7 +1,22 @@
+// Binary literals are grouped by instruction field, e.g. `0b00000_111_00000000`.
+#![allow(clippy::unusual_byte_groupings)]
+
 pub mod assembler;
 pub mod code;
+pub mod debugger;
+pub mod diagnostic;
+pub mod error;
+pub mod expr;
 pub mod lexer;
+pub mod loader;
 pub mod mnemonic;
 pub mod parser;
+pub mod preprocessor;
 pub mod register;
+pub mod sim;
+pub mod source;
+pub mod span;
 pub mod token;
+pub mod trace;
+
+pub use error::{Error, Result, Warning};
@@ -0,0 +1,213 @@
+use crate::{
+    error::{Error, Result},
+    mnemonic::*,
+    parser::{parse_source, parse_source_recovered, ParseOptions},
+    preprocessor::Environment,
+    source::SourceMap,
+    span::Spanned,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Reads a source file and the files it includes with `.include`.
+///
+/// The files are recorded in a `SourceMap` so that diagnostics can show
+/// where they came from; see `Preprocessor` for how they are combined.
+#[derive(Debug, Default)]
+pub struct Loader {
+    options: ParseOptions,
+    environment: Environment,
+    sources: SourceMap,
+}
+
+impl Loader {
+    pub fn new(options: ParseOptions) -> Self {
+        Loader {
+            options,
+            ..Loader::default()
+        }
+    }
+
+    /// Adds a directory to search for included files. The directory of the
+    /// including file is always searched first.
+    pub fn include_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.environment.include_paths.push(path.into());
+        self
+    }
+
+    /// Defines the symbol `name` before the first line, as `.equ` would.
+    /// `value` is the text of an expression.
+    pub fn define<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.environment.defines.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    pub fn into_sources(self) -> SourceMap {
+        self.sources
+    }
+
+    /// Reads and parses the file at `path`, together with the files it
+    /// includes, reporting the errors of all of them at once.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Spanned<Item>>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let file = self.sources.add(path.display().to_string(), source, None);
+
+        parse_source(&mut self.sources, file, &self.environment, self.options)
+    }
+
+    /// Like `load`, but also returns the items of the lines that could be
+    /// read when there are errors; see `parse_source_recovered`. Only a file
+    /// that cannot be read at all is an error.
+    pub fn load_recovered<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(Vec<Spanned<Item>>, Vec<Error>)> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let file = self.sources.add(path.display().to_string(), source, None);
+
+        Ok(parse_source_recovered(
+            &mut self.sources,
+            file,
+            &self.environment,
+            self.options,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, expr::*, register::*, span::FileId};
+    use std::process;
+
+    /// Creates an empty directory for a test, named after it.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pasm-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn nodes(items: Vec<Spanned<Item>>) -> Vec<Item> {
+        items.into_iter().map(|item| item.node).collect()
+    }
+
+    #[test]
+    fn include() {
+        let dir = test_dir("include");
+        fs::create_dir(dir.join("lib")).unwrap();
+        fs::create_dir(dir.join("common")).unwrap();
+        fs::write(dir.join("main.asm"), ".include \"lib/math.asm\"\nNOP\n").unwrap();
+        fs::write(
+            dir.join("lib/math.asm"),
+            ".include \"prologue.asm\"\n:mul\n",
+        )
+        .unwrap();
+        fs::write(dir.join("common/prologue.asm"), "LDI r0, #0\n").unwrap();
+
+        let mut loader = Loader::new(ParseOptions::default()).include_path(dir.join("common"));
+        let items = loader.load(dir.join("main.asm")).unwrap();
+
+        let files: Vec<FileId> = items.iter().map(|item| item.span.start.file).collect();
+        assert_eq!(
+            nodes(items),
+            vec![
+                Item::Directive(Directive::Include("lib/math.asm".into())),
+                Item::Directive(Directive::Include("prologue.asm".into())),
+                Item::instr_i(OpcodeI::LDI, Register::R0, 0),
+                Item::label("mul".into()),
+                Item::instr_r(FunctR::NOP, Register::R0, Register::R0),
+            ]
+        );
+        assert_eq!(
+            files,
+            vec![FileId(0), FileId(1), FileId(2), FileId(1), FileId(0)]
+        );
+
+        let sources = loader.into_sources();
+        let prologue = sources.get(FileId(2)).unwrap();
+        assert!(prologue.name.ends_with("prologue.asm"));
+        assert_eq!(sources.backtrace(FileId(2)).len(), 2);
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = test_dir("include-errors");
+        fs::write(
+            dir.join("main.asm"),
+            ".include \"a.asm\"\n.include \"missing.asm\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("a.asm"), ".include \"b.asm\"\n").unwrap();
+        fs::write(dir.join("b.asm"), ".include \"a.asm\"\nADD r0\n").unwrap();
+
+        let mut loader = Loader::new(ParseOptions::default());
+        let errors = loader.load(dir.join("main.asm")).unwrap_err().into_errors();
+
+        // The errors are in the order the files are read.
+        match &errors[0] {
+            Error::IncludeCycle { path, span } => {
+                assert_eq!(path, "a.asm");
+                assert_eq!(span.start.file, FileId(2));
+            }
+            err => panic!("expected an include cycle error, got {:?}", err),
+        }
+        assert!(matches!(errors[1], Error::UnrecognizedToken { .. }));
+        assert_eq!(errors[1].span().unwrap().start.file, FileId(2));
+        match &errors[2] {
+            Error::IncludeNotFound { path, span } => {
+                assert_eq!(path, "missing.asm");
+                assert_eq!(span.start.file, FileId(0));
+            }
+            err => panic!("expected an include not found error, got {:?}", err),
+        }
+        assert_eq!(errors.len(), 3);
+        assert_eq!(loader.sources().backtrace(FileId(2)).len(), 2);
+    }
+
+    #[test]
+    fn define() {
+        let dir = test_dir("define");
+        fs::write(
+            dir.join("main.asm"),
+            ".ifdef DEBUG\nLDI r0, #LEVEL\n.endif\n",
+        )
+        .unwrap();
+
+        let mut loader = Loader::new(ParseOptions::default())
+            .define("DEBUG", "1")
+            .define("LEVEL", "DEBUG + 1");
+        let items = loader.load(dir.join("main.asm")).unwrap();
+
+        assert_eq!(
+            nodes(items),
+            vec![
+                Item::equ("DEBUG".into(), Expr::Num(1)),
+                Item::equ(
+                    "LEVEL".into(),
+                    Expr::binary(BinaryOp::Add, Expr::Symbol("DEBUG".into()), Expr::Num(1))
+                ),
+                Item::instr_i(OpcodeI::LDI, Register::R0, Expr::Symbol("LEVEL".into())),
+            ]
+        );
+        assert_eq!(
+            loader.sources().get(FileId(1)).unwrap().name,
+            "<command line>"
+        );
+
+        let mut loader = Loader::new(ParseOptions::default());
+        assert!(loader.load(dir.join("main.asm")).unwrap().is_empty());
+    }
+}
@@ -1,59 +1,744 @@
-use core::panic;
 use std::{
-    fs::{self, File},
-    io::{BufWriter, Write},
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Write},
+    path::Path,
+    process,
 };
 
-use clap::Clap;
-use lalrpop_util::ParseError;
-use pasm::{assembler::*, lexer::Lexer, mnemonic::*, parser::poco::FileParser, token::*};
+use clap::{Arg, ArgMatches, Command};
+use pasm::{
+    assembler::*,
+    code::Program,
+    debugger::Debugger,
+    diagnostic::Diagnostic,
+    lexer::Lexer,
+    loader::Loader,
+    parser::{poco::RegisterParser, ParseOptions},
+    register::Register,
+    sim::{Halt, Machine, MachineOptions},
+    source::SourceMap,
+    token::Token,
+    trace::{self, Entry, Format},
+    Error, Result, Warning,
+};
 
-#[derive(Clap, Debug)]
+#[derive(Debug)]
 struct Opts {
     input: String,
+    include_paths: Vec<String>,
+    defines: Vec<(String, String)>,
+    strict_case: bool,
+    pad: u16,
+    stack_pointer: Register,
+    relax: Option<Register>,
+    mode: Mode,
+}
+
+/// What to do with the assembled program.
+#[derive(Debug)]
+enum Mode {
+    /// Writes the memory images.
+    Assemble {
+        output: Option<String>,
+        data: Option<String>,
+    },
+
+    /// Runs the program with the simulator and prints its final state.
+    Run(RunOpts),
+
+    /// Runs the program in the debugger, reading commands from stdin.
+    Debug {
+        machine: MachineOpts,
+
+        /// The number of instructions that can be stepped back.
+        history: usize,
+    },
+}
+
+/// How the simulator is set up.
+#[derive(Debug)]
+struct MachineOpts {
+    /// Data words written before the program starts: `(address, value)`
+    mem: Vec<(u16, u16)>,
+    max_cycles: u64,
+}
+
+impl MachineOpts {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        MachineOpts {
+            mem: matches
+                .get_many::<(u16, u16)>("mem")
+                .map(|mem| mem.copied().collect())
+                .unwrap_or_default(),
+            max_cycles: *matches.get_one::<u64>("max-cycles").unwrap(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RunOpts {
+    machine: MachineOpts,
+    halt: Option<Address>,
+
+    /// The ranges of the data memory to print: `(address, length)`
+    dump: Vec<(u16, u16)>,
+
+    /// Where to write the execution trace.
+    trace: Option<String>,
+    trace_format: Format,
+}
 
-    #[clap(short, long)]
-    output: Option<String>,
+/// An instruction address, written as a number or as a label.
+#[derive(Debug, Clone)]
+enum Address {
+    Number(u16),
+    Label(String),
 }
 
-fn main() -> std::io::Result<()> {
-    let opts = Opts::parse();
+/// The command line of pasm.
+fn cli() -> Command<'static> {
+    Command::new("pasm")
+        .about("Assembler for the Poco instruction set")
+        .args(source_args())
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("data")
+                .short('d')
+                .long("data")
+                .takes_value(true)
+                .help("Where to write the data memory image [default: <output>.data.dat]"),
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("run")
+                .about("Assembles a program and runs it with the simulator")
+                .args(source_args())
+                .args(machine_args())
+                .arg(
+                    Arg::new("halt")
+                        .long("halt")
+                        .takes_value(true)
+                        .value_name("ADDR|LABEL")
+                        .value_parser(parse_address)
+                        .help("Stops before running the instruction at ADDR or LABEL"),
+                )
+                .arg(
+                    Arg::new("dump")
+                        .long("dump")
+                        .takes_value(true)
+                        .value_name("ADDR[..LEN]")
+                        .multiple_occurrences(true)
+                        .use_value_delimiter(true)
+                        .value_parser(parse_range)
+                        .help("Prints LEN words of the data memory from ADDR [default: the words that are not 0]"),
+                )
+                .arg(
+                    Arg::new("trace")
+                        .long("trace")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Writes the instructions run, one per line, to FILE"),
+                )
+                .arg(
+                    Arg::new("trace-format")
+                        .long("trace-format")
+                        .takes_value(true)
+                        .value_parser(["text", "csv", "json"])
+                        .default_value("text")
+                        .help("The format of the trace written with `--trace`"),
+                ),
+        )
+        .subcommand(
+            Command::new("debug")
+                .about("Assembles a program and steps through it in the debugger")
+                .args(source_args())
+                .args(machine_args())
+                .arg(
+                    Arg::new("history")
+                        .long("history")
+                        .takes_value(true)
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1000000")
+                        .help("Remembers the last N instructions run, for `step-back`"),
+                ),
+        )
+        .subcommand(
+            Command::new("trace-diff")
+                .about("Compares an execution trace with one from a testbench and reports where they diverge")
+                .arg(
+                    Arg::new("expected")
+                        .required(true)
+                        .value_name("EXPECTED")
+                        .help("The trace written by `pasm run --trace`"),
+                )
+                .arg(
+                    Arg::new("actual")
+                        .required(true)
+                        .value_name("ACTUAL")
+                        .help("The trace captured from the testbench"),
+                ),
+        )
+}
+
+impl Opts {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.subcommand() {
+            Some(("run", sub)) => Opts::with_mode(
+                sub,
+                Mode::Run(RunOpts {
+                    machine: MachineOpts::from_matches(sub),
+                    halt: sub.get_one::<Address>("halt").cloned(),
+                    dump: sub
+                        .get_many::<(u16, u16)>("dump")
+                        .map(|dump| dump.copied().collect())
+                        .unwrap_or_default(),
+                    trace: sub.value_of("trace").map(|s| s.to_string()),
+                    trace_format: Format::from_name(sub.get_one::<String>("trace-format").unwrap())
+                        .unwrap(),
+                }),
+            ),
+            Some(("debug", sub)) => Opts::with_mode(
+                sub,
+                Mode::Debug {
+                    machine: MachineOpts::from_matches(sub),
+                    history: *sub.get_one::<usize>("history").unwrap(),
+                },
+            ),
+            _ => Opts::with_mode(
+                matches,
+                Mode::Assemble {
+                    output: matches.value_of("output").map(|s| s.to_string()),
+                    data: matches.value_of("data").map(|s| s.to_string()),
+                },
+            ),
+        }
+    }
+
+    /// Reads the options shared by all modes.
+    fn with_mode(matches: &ArgMatches, mode: Mode) -> Self {
+        Opts {
+            input: matches.value_of("input").unwrap().to_string(),
+            include_paths: matches
+                .values_of("include-path")
+                .map(|paths| paths.map(|path| path.to_string()).collect())
+                .unwrap_or_default(),
+            defines: matches
+                .get_many::<(String, String)>("define")
+                .map(|defines| defines.cloned().collect())
+                .unwrap_or_default(),
+            strict_case: matches.is_present("strict-case"),
+            pad: matches
+                .get_one::<u16>("pad")
+                .copied()
+                .unwrap_or_else(|| AssembleOptions::default().pad),
+            stack_pointer: matches
+                .get_one::<Register>("stack-pointer")
+                .copied()
+                .unwrap_or_else(|| AssembleOptions::default().stack_pointer),
+            relax: matches.get_one::<Register>("relax").copied(),
+            mode,
+        }
+    }
+}
+
+/// The arguments that choose the source and how it is assembled.
+fn source_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("input").required(true),
+        Arg::new("include-path")
+            .short('I')
+            .long("include-path")
+            .takes_value(true)
+            .value_name("DIR")
+            .multiple_occurrences(true)
+            .help("A directory to search for files included with `.include`"),
+        Arg::new("define")
+            .short('D')
+            .long("define")
+            .takes_value(true)
+            .value_name("NAME[=VALUE]")
+            .multiple_occurrences(true)
+            .value_parser(parse_define)
+            .help("Defines a symbol for `.if` and `.ifdef`, as `.equ` would [default VALUE: 1]"),
+        Arg::new("pad")
+            .long("pad")
+            .takes_value(true)
+            .value_name("WORD")
+            .value_parser(parse_word)
+            .help("The word that fills gaps left by `.org` and `.align` [default: NOP]"),
+        Arg::new("stack-pointer")
+            .long("stack-pointer")
+            .takes_value(true)
+            .value_name("REGISTER")
+            .value_parser(parse_register)
+            .help("The register `PUSH` and `POP` use as the stack pointer [default: r6]"),
+        Arg::new("relax")
+            .long("relax")
+            .takes_value(true)
+            .value_name("REGISTER")
+            .min_values(0)
+            .require_equals(true)
+            .default_missing_value("r5")
+            .value_parser(parse_register)
+            .help("Lengthen branches and jumps that do not reach their targets, jumping far through REGISTER [default: r5]"),
+        Arg::new("strict-case")
+            .long("strict-case")
+            .help("Only accept uppercase mnemonics and lowercase registers"),
+    ]
+}
+
+/// The arguments that set up the simulator.
+fn machine_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("mem")
+            .long("mem")
+            .takes_value(true)
+            .value_name("ADDR=VALUE")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .value_parser(parse_assignment)
+            .help("Writes VALUE to the data memory at ADDR before running"),
+        Arg::new("max-cycles")
+            .long("max-cycles")
+            .takes_value(true)
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("1000000")
+            .help("Stops after running N instructions"),
+    ]
+}
+
+/// Parses a word written like a number in the source: `0`, `0xffff`,
+/// `0b10100_00000000000`.
+fn parse_word(s: &str) -> std::result::Result<u16, String> {
+    let mut lexer = Lexer::new(s);
+    match (lexer.next_token(), lexer.next_token()) {
+        (Ok(Some(Token::Num(value))), Ok(None)) => {
+            u16::try_from(value).map_err(|_| format!("`{}` does not fit in 16 bits", s))
+        }
+        _ => Err(format!("`{}` is not a number", s)),
+    }
+}
+
+/// Parses a data value: a word, or a negative number stored in two's
+/// complement: `-5`.
+fn parse_value(s: &str) -> std::result::Result<u16, String> {
+    match s.strip_prefix('-') {
+        Some(magnitude) => match parse_word(magnitude)? {
+            magnitude if magnitude <= 0x8000 => Ok(magnitude.wrapping_neg()),
+            _ => Err(format!("`{}` does not fit in 16 bits", s)),
+        },
+        None => parse_word(s),
+    }
+}
+
+/// Splits `ADDR=VALUE` into the address and the value.
+fn parse_assignment(s: &str) -> std::result::Result<(u16, u16), String> {
+    let (addr, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` is not of the form ADDR=VALUE", s))?;
+
+    Ok((parse_word(addr)?, parse_value(value)?))
+}
+
+/// Splits `ADDR..LEN` into the address and the length, which is 1 when it
+/// is left out.
+fn parse_range(s: &str) -> std::result::Result<(u16, u16), String> {
+    match s.split_once("..") {
+        Some((addr, len)) => Ok((parse_word(addr)?, parse_word(len)?)),
+        None => Ok((parse_word(s)?, 1)),
+    }
+}
+
+/// Parses an instruction address or the label of one.
+fn parse_address(s: &str) -> std::result::Result<Address, String> {
+    if let Ok(addr) = parse_word(s) {
+        return Ok(Address::Number(addr));
+    }
+
+    let mut lexer = Lexer::new(s);
+    match (lexer.next_token(), lexer.next_token()) {
+        (Ok(Some(Token::Ident(label))), Ok(None)) => Ok(Address::Label(label)),
+        _ => Err(format!("`{}` is neither an address nor a label", s)),
+    }
+}
+
+/// Splits `NAME=VALUE` into the name and the value, which is `1` when it
+/// is left out.
+fn parse_define(s: &str) -> std::result::Result<(String, String), String> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "1"));
+
+    let mut lexer = Lexer::new(name);
+    match (lexer.next_token(), lexer.next_token()) {
+        (Ok(Some(Token::Ident(_))), Ok(None)) => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("`{}` is not a symbol name", name)),
+    }
+}
+
+/// Parses a register written like in the source: `r6`.
+fn parse_register(s: &str) -> std::result::Result<Register, String> {
+    RegisterParser::new()
+        .parse(&mut Vec::new(), Lexer::new(s))
+        .map_err(|_| format!("`{}` is not a register", s))
+}
+
+fn main() {
+    let matches = cli().get_matches();
+    if let Some(("trace-diff", sub)) = matches.subcommand() {
+        let expected = sub.value_of("expected").unwrap();
+        let actual = sub.value_of("actual").unwrap();
+        let sources = SourceMap::new();
+        match trace_diff(expected, actual) {
+            Ok(true) => process::exit(0),
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                Reporter::new(expected, &sources).error(err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let opts = Opts::from_matches(&matches);
 
     let input_path = &opts.input;
-    let output_path = match opts.output {
-        Some(output) => output,
-        None => "a.dat".to_string(),
+    let options = ParseOptions {
+        strict_case: opts.strict_case,
+    };
+    let loader = opts
+        .include_paths
+        .iter()
+        .fold(Loader::new(options), |loader, path| {
+            loader.include_path(path)
+        });
+    let mut loader = opts
+        .defines
+        .iter()
+        .fold(loader, |loader, (name, value)| loader.define(name, value));
+    let items = loader.load_recovered(input_path);
+
+    let mut reporter = Reporter::new(input_path, loader.sources());
+    let (items, parse_errors) = match items {
+        Ok(items) => items,
+        Err(err) => {
+            reporter.error(err);
+            process::exit(1);
+        }
     };
 
-    let input_file = fs::read_to_string(input_path)?;
+    // The lines that could not be read may refer to any label.
+    if parse_errors.is_empty() {
+        for warning in unused_labels(&items) {
+            reporter.warning(&warning);
+        }
+    }
 
-    let items = match parse_file(input_file.as_str()) {
-        Ok(result) => result,
+    let assemble_options = AssembleOptions {
+        pad: opts.pad,
+        stack_pointer: opts.stack_pointer,
+        relax: opts.relax,
+    };
+    // The items that could be read are assembled even when others could
+    // not, so that all the errors are reported at once.
+    let program = match assemble_recovered(items, parse_errors, assemble_options) {
+        Ok(program) => program,
         Err(err) => {
-            println!("Parse error");
-            println!("{:?}", &err);
+            reporter.error(err);
+            process::exit(1);
+        }
+    };
 
-            panic!();
+    let result = match &opts.mode {
+        Mode::Assemble { output, data } => write_program(
+            &program,
+            output.as_deref().unwrap_or("a.dat"),
+            data.as_deref(),
+        ),
+        Mode::Run(run_opts) => run(&program, run_opts),
+        Mode::Debug { machine, history } => debug(&program, loader.sources(), machine, *history),
+    };
+    if let Err(err) = result {
+        reporter.error(err);
+        process::exit(1);
+    }
+}
+
+fn write_program(program: &Program, output_path: &str, data_path: Option<&str>) -> Result<()> {
+    let lines = program.codes.iter().map(|code| code.get_line(true, true));
+    write_image(output_path, lines)?;
+
+    // The data image is only written when there is data or it is asked for.
+    if !program.data.is_empty() || data_path.is_some() {
+        let data_path = match data_path {
+            Some(data_path) => data_path.to_string(),
+            None => Path::new(output_path)
+                .with_extension("data.dat")
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        let lines = program.data.iter().map(|data| data.get_line(true, true));
+        write_image(&data_path, lines)?;
+    }
+
+    Ok(())
+}
+
+/// Runs `program` until it halts and prints the registers and the data
+/// memory. The state is printed even when an instruction cannot be run.
+fn run(program: &Program, opts: &RunOpts) -> Result<()> {
+    let halt = match &opts.halt {
+        Some(Address::Number(addr)) => Some(*addr),
+        Some(Address::Label(label)) => match program.labels.get(label) {
+            Some(addr) => Some(*addr),
+            None => {
+                return Err(Error::UnknownLabel {
+                    label: label.clone(),
+                    option: "--halt".to_string(),
+                })
+            }
+        },
+        None => None,
+    };
+
+    let mut trace = match &opts.trace {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    if let (Some(writer), Some(header)) = (&mut trace, opts.trace_format.header()) {
+        writeln!(writer, "{}", header)?;
+    }
+
+    // A write that fails is reported once the state has been printed.
+    let mut trace_error = None;
+    let mut machine = boot(program, &opts.machine, MachineOptions::default())?;
+    let result = machine.run_traced(
+        opts.machine.max_cycles,
+        |pc| Some(pc) == halt,
+        |cycle, step| {
+            if let (Some(writer), None) = (&mut trace, &trace_error) {
+                let entry = Entry::new(cycle, step, program);
+                if let Err(err) = writeln!(writer, "{}", opts.trace_format.format(&entry)) {
+                    trace_error = Some(err);
+                }
+            }
+        },
+    );
+    let reason = match &result {
+        Ok(Halt::Loop) => "the instruction jumps to itself",
+        Ok(Halt::Address) => "reached the halt address",
+        Ok(Halt::Limit) => "reached the cycle limit",
+        Err(_) => "the instruction cannot be run",
+    };
+    println!(
+        "halted at {} after {} cycles: {}",
+        machine.pc(),
+        machine.cycles(),
+        reason
+    );
+
+    for reg in Register::ALL.iter() {
+        let value = machine.register(*reg);
+        println!("{}  = {:#06x} {:>6}", reg, value, value as i16);
+    }
+
+    let addresses: Vec<u16> = if opts.dump.is_empty() {
+        (0..machine.dmem().len())
+            .filter(|&addr| machine.dmem()[addr] != 0)
+            .map(|addr| addr as u16)
+            .collect()
+    } else {
+        opts.dump
+            .iter()
+            .flat_map(|&(addr, len)| (0..len).map(move |offset| addr.wrapping_add(offset)))
+            .collect()
+    };
+    for addr in addresses {
+        let value = machine.read(addr)?;
+        println!("[{:#06x}] = {:#06x} {:>6}", addr, value, value as i16);
+    }
+
+    if let Some(err) = trace_error {
+        return Err(err.into());
+    }
+    if let Some(mut writer) = trace {
+        writer.flush()?;
+    }
+
+    result.map(|_| ())
+}
+
+/// Compares the trace in `expected_path` with the one in `actual_path` and
+/// prints the first instruction where they differ. Returns whether the
+/// traces match.
+fn trace_diff(expected_path: &str, actual_path: &str) -> Result<bool> {
+    let expected = trace::parse(expected_path, &std::fs::read_to_string(expected_path)?)?;
+    let actual = trace::parse(actual_path, &std::fs::read_to_string(actual_path)?)?;
+
+    let divergence = match trace::diff(&expected, &actual) {
+        Some(divergence) => divergence,
+        None => {
+            println!("the traces match for {} instructions", expected.len());
+            return Ok(true);
+        }
+    };
+
+    let fields = match (&divergence.expected, &divergence.actual) {
+        (Some(expected), Some(actual)) => {
+            let fields: Vec<&str> = [
+                ("pc", expected.pc != actual.pc),
+                ("code", expected.code != actual.code),
+                ("write", expected.write != actual.write),
+            ]
+            .iter()
+            .filter(|(_, differs)| *differs)
+            .map(|(field, _)| *field)
+            .collect();
+            format!("the instructions differ in {}", fields.join(", "))
         }
+        (Some(_), None) => format!("`{}` ends early", actual_path),
+        (None, _) => format!("`{}` ends early", expected_path),
+    };
+    println!(
+        "the traces diverge after {} instructions: {}",
+        divergence.index, fields
+    );
+
+    let line = |entry: Option<&Entry>| match entry {
+        Some(entry) => entry.to_string(),
+        None => "(end of trace)".to_string(),
     };
+    if let Some(index) = divergence.index.checked_sub(1) {
+        println!("last match: {}", line(expected.get(index)));
+    }
+    println!("  expected: {}", line(divergence.expected.as_ref()));
+    println!("    actual: {}", line(divergence.actual.as_ref()));
 
-    let (mnemonics, table) = convert(items);
-    let codes = assemble(mnemonics, &table);
+    Ok(false)
+}
 
-    let output_file = File::create(output_path)?;
-    let mut output_writer = BufWriter::new(output_file);
+/// Steps through `program` with commands read from stdin until `quit` or
+/// the end of the input. An empty line repeats the last command.
+fn debug(program: &Program, sources: &SourceMap, opts: &MachineOpts, history: usize) -> Result<()> {
+    let options = MachineOptions {
+        history,
+        ..MachineOptions::default()
+    };
+    let machine = boot(program, opts, options)?;
+    let mut debugger = Debugger::new(program, sources, machine).max_cycles(opts.max_cycles);
+    println!("{}", debugger.execute("where").unwrap());
 
-    for line in codes {
-        let line_str = line.get_line(true, true);
-        output_writer.write_fmt(format_args!("{}\n", line_str))?;
+    let stdin = io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(pasm) ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+        let command = match line.trim() {
+            "" => last.clone(),
+            command => command.to_string(),
+        };
+        if matches!(command.as_str(), "quit" | "q") {
+            break;
+        }
+
+        match debugger.execute(&command) {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => println!("{}", output),
+            Err(message) => println!("error: {}", message),
+        }
+        last = command;
     }
 
-    output_writer.flush()?;
+    Ok(())
+}
+
+/// Creates a machine with `program` loaded and the data words of `opts`
+/// written.
+fn boot(program: &Program, opts: &MachineOpts, options: MachineOptions) -> Result<Machine> {
+    let mut machine = Machine::with_program(program, options)?;
+    for &(addr, value) in &opts.mem {
+        machine.write(addr, value)?;
+    }
+
+    Ok(machine)
+}
+
+/// Writes a memory image in the format read by `$readmemb`, one word per line.
+fn write_image<I>(path: &str, lines: I) -> Result<()>
+where
+    I: IntoIterator<Item = String>,
+{
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    for line in lines {
+        writer.write_fmt(format_args!("{}\n", line))?;
+    }
+
+    writer.flush()?;
 
     Ok(())
 }
 
-fn parse_file(input: &str) -> Result<Vec<Item>, ParseError<(), Token, ()>> {
-    let lexer = Lexer::new(input);
-    FileParser::new().parse(lexer)
+/// Prints diagnostics for the source files of a program to stderr.
+struct Reporter<'a> {
+    /// The file given on the command line.
+    file_name: &'a str,
+    sources: &'a SourceMap,
+    color: bool,
+    count: usize,
+}
+
+impl<'a> Reporter<'a> {
+    fn new(file_name: &'a str, sources: &'a SourceMap) -> Self {
+        Reporter {
+            file_name,
+            sources,
+            color: io::stderr().is_terminal(),
+            count: 0,
+        }
+    }
+
+    fn emit(&mut self, diagnostic: Diagnostic) {
+        if self.count > 0 {
+            eprintln!();
+        }
+        self.count += 1;
+
+        eprint!("{}", diagnostic.render_sources(self.sources, self.color));
+    }
+
+    fn warning(&mut self, warning: &Warning) {
+        self.emit(Diagnostic::from(warning));
+    }
+
+    fn error(&mut self, err: Error) {
+        let mut errors = err.into_errors();
+        self.sources.sort_errors(&mut errors);
+        for err in &errors {
+            self.emit(Diagnostic::from(err));
+        }
+
+        if errors.len() > 1 {
+            eprintln!();
+            eprintln!(
+                "error: could not assemble `{}` due to {} previous errors",
+                self.file_name,
+                errors.len()
+            );
+        }
+    }
 }
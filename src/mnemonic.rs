@@ -1,10 +1,18 @@
-use crate::register::Register;
-use std::fmt::{self, Display, Formatter};
+use crate::{
+    expr::{Expr, UnaryOp},
+    register::Register,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::RangeInclusive,
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Item {
     Label(String),
     Mnemonic(Mnemonic),
+    Pseudo(Pseudo),
+    Directive(Directive),
 }
 
 impl Item {
@@ -16,7 +24,7 @@ impl Item {
         Self::Mnemonic(Mnemonic::instr_r(funct, dst, src))
     }
 
-    pub fn instr_i(opcode: OpcodeI, dst: Register, immediate: i8) -> Self {
+    pub fn instr_i<E: Into<Expr>>(opcode: OpcodeI, dst: Register, immediate: E) -> Self {
         Self::Mnemonic(Mnemonic::instr_i(opcode, dst, immediate))
     }
 
@@ -31,6 +39,41 @@ impl Item {
     pub fn instr_jr(funct: FunctJR, dst: Register) -> Self {
         Self::Mnemonic(Mnemonic::instr_jr(funct, dst))
     }
+
+    pub fn equ(name: String, value: Expr) -> Self {
+        Self::Directive(Directive::Equ(name, value))
+    }
+
+    /// Renames the labels and symbols the item refers to that `rename` gives
+    /// a new name. The label or symbol the item defines keeps its name.
+    pub fn rename_references<F>(&mut self, rename: &F)
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let rename_label = |label: &mut String| {
+            if let Some(name) = rename(label) {
+                *label = name;
+            }
+        };
+
+        match self {
+            Item::Mnemonic(Mnemonic::I(instr)) => instr.immediate.rename(rename),
+            Item::Mnemonic(Mnemonic::B(instr)) => rename_label(&mut instr.label),
+            Item::Mnemonic(Mnemonic::J(instr)) => rename_label(&mut instr.label),
+            Item::Pseudo(Pseudo::LI(_, value)) => value.rename(rename),
+            Item::Pseudo(Pseudo::LA(_, label))
+            | Item::Pseudo(Pseudo::B(label))
+            | Item::Pseudo(Pseudo::CALL(label)) => rename_label(label),
+            Item::Directive(Directive::Equ(_, value))
+            | Item::Directive(Directive::Space(value))
+            | Item::Directive(Directive::Org(value))
+            | Item::Directive(Directive::Align(value)) => value.rename(rename),
+            Item::Directive(Directive::Word(values)) | Item::Directive(Directive::Byte(values)) => {
+                values.iter_mut().for_each(|value| value.rename(rename))
+            }
+            _ => {}
+        }
+    }
 }
 
 impl Display for Item {
@@ -38,6 +81,249 @@ impl Display for Item {
         match &self {
             Item::Label(l) => write!(f, ":{}", l),
             Item::Mnemonic(m) => write!(f, "{}", m),
+            Item::Pseudo(p) => write!(f, "{}", p),
+            Item::Directive(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+/// An assembler directive, written with a leading `.`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Directive {
+    /// Defines a symbolic constant: `.equ NAME, value`. `.set` is a synonym.
+    /// A symbol may be redefined; each instruction sees the value defined
+    /// last before it, or the final value if it is only defined later.
+    Equ(String, Expr),
+
+    /// Switches to a section: `.text`, `.data`
+    Section(Section),
+
+    /// Stores 16-bit words: `.word 1, 2`
+    Word(Vec<Expr>),
+
+    /// Stores 8-bit values, one per word: `.byte 1, 2`
+    Byte(Vec<Expr>),
+
+    /// Reserves zeroed words: `.space 4`
+    Space(Expr),
+
+    /// Stores one character per word: `.ascii "abc"`
+    Ascii(String),
+
+    /// Stores one character per word followed by a zero: `.string "abc"`
+    String(String),
+
+    /// Moves the current section to an address: `.org 0x10`
+    Org(Expr),
+
+    /// Moves the current section to the next multiple of a number of
+    /// words: `.align 4`
+    Align(Expr),
+
+    /// Reads another source file in place of the directive:
+    /// `.include "lib/math.asm"`. The included items follow the directive,
+    /// see `Loader`.
+    Include(String),
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let list = |values: &[Expr]| {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            Directive::Equ(name, value) => write!(f, ".equ {}, {}", name, value),
+            Directive::Section(section) => write!(f, "{}", section),
+            Directive::Word(values) => write!(f, ".word {}", list(values)),
+            Directive::Byte(values) => write!(f, ".byte {}", list(values)),
+            Directive::Space(size) => write!(f, ".space {}", size),
+            Directive::Ascii(text) => write!(f, ".ascii {:?}", text),
+            Directive::String(text) => write!(f, ".string {:?}", text),
+            Directive::Org(addr) => write!(f, ".org {}", addr),
+            Directive::Align(alignment) => write!(f, ".align {}", alignment),
+            Directive::Include(path) => write!(f, ".include {:?}", path),
+        }
+    }
+}
+
+/// The memory an item is placed in.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Section {
+    /// The instruction memory.
+    Text,
+
+    /// The data memory.
+    Data,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Section::Text => write!(f, ".text"),
+            Section::Data => write!(f, ".data"),
+        }
+    }
+}
+
+/// The register `JAL` and `JALR` save the return address in.
+pub const LINK_REGISTER: Register = Register::R7;
+
+/// An instruction that the assembler expands into a short sequence of real
+/// instructions.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pseudo {
+    /// Loads any 16-bit value: `LI r0, #0x1234`
+    LI(Register, Expr),
+
+    /// Loads the address of a label: `LA r0, table`
+    LA(Register, String),
+
+    /// Sets a register to 0: `CLR r0`
+    CLR(Register),
+
+    /// Inverts the bits of the second register into the first: `NOT r0, r1`.
+    /// `NOT r0` inverts `r0` in place.
+    NOT(Register, Register),
+
+    /// Negates the second register into the first: `NEG r0, r1`. `NEG r0`
+    /// negates `r0` in place.
+    NEG(Register, Register),
+
+    /// Adds 1 to a register: `INC r0`
+    INC(Register),
+
+    /// Subtracts 1 from a register: `DEC r0`
+    DEC(Register),
+
+    /// Branches unconditionally: `B loop`
+    B(String),
+
+    /// Calls a subroutine, saving the return address in `r7`: `CALL f`
+    CALL(String),
+
+    /// Returns from a subroutine called with `CALL`: `RET`
+    RET,
+
+    /// Pushes a register onto the stack: `PUSH r0`
+    PUSH(Register),
+
+    /// Pops a register from the stack: `POP r0`
+    POP(Register),
+}
+
+impl Pseudo {
+    /// Returns the instructions the pseudo-instruction stands for.
+    ///
+    /// The stack grows downwards from `stack_pointer`, which points at the
+    /// last value pushed. `LI` takes the shortest sequence when its value is
+    /// already known, and `LDHI` followed by `ADDIU` otherwise, since `LDHI`
+    /// clears the lower byte.
+    pub fn expand(&self, stack_pointer: Register) -> Vec<Mnemonic> {
+        let push = |reg| {
+            vec![
+                Mnemonic::instr_i(OpcodeI::ADDI, stack_pointer, -1),
+                Mnemonic::instr_r(FunctR::ST, reg, stack_pointer),
+            ]
+        };
+        let pop = |reg| {
+            vec![
+                Mnemonic::instr_r(FunctR::LD, reg, stack_pointer),
+                Mnemonic::instr_i(OpcodeI::ADDI, stack_pointer, 1),
+            ]
+        };
+        let load_word = |dst, value: &Expr| {
+            vec![
+                Mnemonic::instr_i(OpcodeI::LDHI, dst, Expr::unary(UnaryOp::Hi, value.clone())),
+                Mnemonic::instr_i(OpcodeI::ADDIU, dst, Expr::unary(UnaryOp::Lo, value.clone())),
+            ]
+        };
+        // `dst = initial - src`, saving a helper register on the stack when
+        // the operation is in place.
+        let subtract_from = |initial: i64, dst, src| {
+            if dst != src {
+                return vec![
+                    Mnemonic::instr_i(OpcodeI::LDI, dst, initial),
+                    Mnemonic::instr_r(FunctR::SUB, dst, src),
+                ];
+            }
+
+            let helper = [Register::R0, Register::R1, Register::R2]
+                .iter()
+                .copied()
+                .find(|&reg| reg != dst && reg != stack_pointer)
+                .unwrap();
+            let mut instrs = push(helper);
+            instrs.extend(vec![
+                Mnemonic::instr_r(FunctR::MV, helper, dst),
+                Mnemonic::instr_i(OpcodeI::LDI, dst, initial),
+                Mnemonic::instr_r(FunctR::SUB, dst, helper),
+            ]);
+            instrs.extend(pop(helper));
+            instrs
+        };
+
+        match self {
+            Pseudo::LI(dst, Expr::Num(value)) => {
+                if OpcodeI::LDI.range().contains(value) {
+                    vec![Mnemonic::instr_i(OpcodeI::LDI, *dst, *value)]
+                } else if OpcodeI::LDIU.range().contains(value) {
+                    vec![Mnemonic::instr_i(OpcodeI::LDIU, *dst, *value)]
+                } else if value & 0xff == 0 {
+                    vec![Mnemonic::instr_i(OpcodeI::LDHI, *dst, (value >> 8) & 0xff)]
+                } else {
+                    vec![
+                        Mnemonic::instr_i(OpcodeI::LDHI, *dst, (value >> 8) & 0xff),
+                        Mnemonic::instr_i(OpcodeI::ADDIU, *dst, value & 0xff),
+                    ]
+                }
+            }
+            Pseudo::LI(dst, value) => load_word(*dst, value),
+            Pseudo::LA(dst, label) => load_word(*dst, &Expr::Symbol(label.clone())),
+            Pseudo::CLR(dst) => vec![Mnemonic::instr_i(OpcodeI::LDI, *dst, 0)],
+            Pseudo::NOT(dst, src) => subtract_from(-1, *dst, *src),
+            Pseudo::NEG(dst, src) => subtract_from(0, *dst, *src),
+            Pseudo::INC(dst) => vec![Mnemonic::instr_i(OpcodeI::ADDI, *dst, 1)],
+            Pseudo::DEC(dst) => vec![Mnemonic::instr_i(OpcodeI::ADDI, *dst, -1)],
+            Pseudo::B(label) => vec![Mnemonic::instr_j(OpcodeJ::JMP, label.clone())],
+            Pseudo::CALL(label) => vec![Mnemonic::instr_j(OpcodeJ::JAL, label.clone())],
+            Pseudo::RET => vec![Mnemonic::instr_jr(FunctJR::JR, LINK_REGISTER)],
+            Pseudo::PUSH(src) => push(*src),
+            Pseudo::POP(dst) => pop(*dst),
+        }
+    }
+
+    /// Returns the labels and symbols the pseudo-instruction refers to.
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Pseudo::LI(_, value) => value.symbols(),
+            Pseudo::LA(_, label) | Pseudo::B(label) | Pseudo::CALL(label) => vec![label.as_str()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Display for Pseudo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pseudo::LI(dst, value) => write!(f, "LI {}, #{}", dst, value),
+            Pseudo::LA(dst, label) => write!(f, "LA {}, {}", dst, label),
+            Pseudo::CLR(dst) => write!(f, "CLR {}", dst),
+            Pseudo::NOT(dst, src) if dst == src => write!(f, "NOT {}", dst),
+            Pseudo::NOT(dst, src) => write!(f, "NOT {}, {}", dst, src),
+            Pseudo::NEG(dst, src) if dst == src => write!(f, "NEG {}", dst),
+            Pseudo::NEG(dst, src) => write!(f, "NEG {}, {}", dst, src),
+            Pseudo::INC(dst) => write!(f, "INC {}", dst),
+            Pseudo::DEC(dst) => write!(f, "DEC {}", dst),
+            Pseudo::B(label) => write!(f, "B {}", label),
+            Pseudo::CALL(label) => write!(f, "CALL {}", label),
+            Pseudo::RET => write!(f, "RET"),
+            Pseudo::PUSH(src) => write!(f, "PUSH {}", src),
+            Pseudo::POP(dst) => write!(f, "POP {}", dst),
         }
     }
 }
@@ -56,11 +342,11 @@ impl Mnemonic {
         Mnemonic::R(InstructionR { funct, dst, src })
     }
 
-    pub fn instr_i(opcode: OpcodeI, dst: Register, immediate: i8) -> Self {
+    pub fn instr_i<E: Into<Expr>>(opcode: OpcodeI, dst: Register, immediate: E) -> Self {
         Mnemonic::I(InstructionI {
             opcode,
             dst,
-            immediate,
+            immediate: immediate.into(),
         })
     }
 
@@ -99,8 +385,8 @@ pub struct InstructionR {
 impl Display for InstructionR {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self.funct {
-            FunctR::LD => write!(f, "{} {}, ({})", self.funct, self.dst, self.src),
-            FunctR::ST => write!(f, "{} {}, ({})", self.funct, self.src, self.dst),
+            // `ST` keeps the stored register in `dst` and the address in `src`.
+            FunctR::LD | FunctR::ST => write!(f, "{} {}, ({})", self.funct, self.dst, self.src),
             _ => write!(f, "{} {}, {}", self.funct, self.dst, self.src),
         }
     }
@@ -135,6 +421,22 @@ impl FunctR {
             FunctR::LD => 0b01001,
         }
     }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0b00000 => Some(FunctR::NOP),
+            0b00001 => Some(FunctR::MV),
+            0b00010 => Some(FunctR::AND),
+            0b00011 => Some(FunctR::OR),
+            0b00100 => Some(FunctR::SL),
+            0b00101 => Some(FunctR::SR),
+            0b00110 => Some(FunctR::ADD),
+            0b00111 => Some(FunctR::SUB),
+            0b01000 => Some(FunctR::ST),
+            0b01001 => Some(FunctR::LD),
+            _ => None,
+        }
+    }
 }
 
 impl Display for FunctR {
@@ -158,7 +460,7 @@ impl Display for FunctR {
 pub struct InstructionI {
     pub opcode: OpcodeI,
     pub dst: Register,
-    pub immediate: i8,
+    pub immediate: Expr,
 }
 
 impl Display for InstructionI {
@@ -186,6 +488,26 @@ impl OpcodeI {
             OpcodeI::LDHI => 0b01010,
         }
     }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0b01000 => Some(OpcodeI::LDI),
+            0b01001 => Some(OpcodeI::LDIU),
+            0b01100 => Some(OpcodeI::ADDI),
+            0b01101 => Some(OpcodeI::ADDIU),
+            0b01010 => Some(OpcodeI::LDHI),
+            _ => None,
+        }
+    }
+
+    /// Returns the values the 8-bit immediate field accepts: signed for
+    /// `LDI` and `ADDI`, unsigned for the others.
+    pub fn range(&self) -> RangeInclusive<i64> {
+        match &self {
+            OpcodeI::LDI | OpcodeI::ADDI => i8::MIN.into()..=i8::MAX.into(),
+            OpcodeI::LDIU | OpcodeI::ADDIU | OpcodeI::LDHI => u8::MIN.into()..=u8::MAX.into(),
+        }
+    }
 }
 
 impl Display for OpcodeI {
@@ -230,6 +552,26 @@ impl OpcodeB {
             OpcodeB::BMI => 0b10011,
         }
     }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0b10000 => Some(OpcodeB::BEZ),
+            0b10001 => Some(OpcodeB::BNZ),
+            0b10010 => Some(OpcodeB::BPL),
+            0b10011 => Some(OpcodeB::BMI),
+            _ => None,
+        }
+    }
+
+    /// Returns the branch taken exactly when this one is not.
+    pub fn inverse(&self) -> Self {
+        match self {
+            OpcodeB::BEZ => OpcodeB::BNZ,
+            OpcodeB::BNZ => OpcodeB::BEZ,
+            OpcodeB::BPL => OpcodeB::BMI,
+            OpcodeB::BMI => OpcodeB::BPL,
+        }
+    }
 }
 
 impl Display for OpcodeB {
@@ -268,6 +610,14 @@ impl OpcodeJ {
             OpcodeJ::JAL => 0b10101,
         }
     }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0b10100 => Some(OpcodeJ::JMP),
+            0b10101 => Some(OpcodeJ::JAL),
+            _ => None,
+        }
+    }
 }
 
 impl Display for OpcodeJ {
@@ -304,6 +654,14 @@ impl FunctJR {
             FunctJR::JALR => 0b11000,
         }
     }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0b01010 => Some(FunctJR::JR),
+            0b11000 => Some(FunctJR::JALR),
+            _ => None,
+        }
+    }
 }
 
 impl Display for FunctJR {
@@ -1,16 +1,170 @@
-use lalrpop_util::lalrpop_mod;
+use crate::{
+    error::{Error, Result},
+    lexer::Lexer,
+    mnemonic::Item,
+    preprocessor::{Environment, Preprocessor},
+    source::SourceMap,
+    span::{FileId, Location, Spanned},
+    token::Token,
+};
+use lalrpop_util::{lalrpop_mod, ParseError};
 
-lalrpop_mod!(pub poco);
+lalrpop_mod!(
+    #[allow(clippy::all)]
+    pub poco
+);
+
+/// Options that change how the source is read.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParseOptions {
+    /// Only accept uppercase mnemonics and lowercase registers.
+    pub strict_case: bool,
+}
+
+pub fn parse_file(input: &str) -> Result<Vec<Spanned<Item>>> {
+    parse_file_with(input, ParseOptions::default())
+}
+
+/// Parses `input`, expanding macros. Included files are searched relative
+/// to the current directory.
+pub fn parse_file_with(input: &str, options: ParseOptions) -> Result<Vec<Spanned<Item>>> {
+    let mut sources = SourceMap::new();
+    let file = sources.add("", input, None);
+
+    parse_source(&mut sources, file, &Environment::default(), options)
+}
+
+/// Parses the text of `file` with the `Preprocessor`, which expands macros
+/// and conditions and reads the files it includes. The included files and
+/// the expansions are added to `sources`, so that the spans refer to them.
+pub fn parse_source(
+    sources: &mut SourceMap,
+    file: FileId,
+    environment: &Environment,
+    options: ParseOptions,
+) -> Result<Vec<Spanned<Item>>> {
+    let (items, errors) = parse_source_recovered(sources, file, environment, options);
+    if errors.is_empty() {
+        Ok(items)
+    } else {
+        Err(Error::from_errors(errors))
+    }
+}
+
+/// Like `parse_source`, but also returns the items of the lines that could
+/// be read when there are errors, so that the assembler can still check
+/// them. The errors are in source order.
+pub fn parse_source_recovered(
+    sources: &mut SourceMap,
+    file: FileId,
+    environment: &Environment,
+    options: ParseOptions,
+) -> (Vec<Spanned<Item>>, Vec<Error>) {
+    let mut errors = Vec::new();
+    let mut preprocessor_errors = Vec::new();
+
+    let tokens = Preprocessor::new(
+        sources,
+        file,
+        environment,
+        options,
+        &mut preprocessor_errors,
+    );
+    let result = poco::FileParser::new().parse(&mut errors, tokens);
+    let (items, mut errors) = recover(result, errors, preprocessor_errors);
+    sources.sort_errors(&mut errors);
+
+    (items.unwrap_or_default(), errors)
+}
+
+pub fn parse_item(input: &str) -> Result<Spanned<Item>> {
+    parse_item_with(input, ParseOptions::default())
+}
+
+pub fn parse_item_with(input: &str, options: ParseOptions) -> Result<Spanned<Item>> {
+    let mut errors = Vec::new();
+    let mut lex_errors = Vec::new();
+
+    let tokens = tokens(input, FileId::default(), options, &mut lex_errors);
+    let result = poco::ItemParser::new().parse(&mut errors, tokens);
+    finish(result, errors, lex_errors)
+}
+
+/// Lexes `input`, moving invalid tokens into `lex_errors` so that the parser
+/// sees the rest of the input.
+///
+/// An invalid number or string is replaced with `0` or `""` rather than
+/// dropped, so that it is not reported again as a missing operand.
+pub(crate) fn tokens<'a>(
+    input: &'a str,
+    file: FileId,
+    options: ParseOptions,
+    lex_errors: &'a mut Vec<Error>,
+) -> impl Iterator<Item = (Location, Token, Location)> + 'a {
+    let lexer = Lexer::new(input)
+        .strict_case(options.strict_case)
+        .file(file);
+
+    lexer.filter_map(move |token| match token {
+        Ok(token) => Some(token),
+        Err(err) => {
+            let placeholder = match err {
+                Error::InvalidNumber { span } => Some((span.start, Token::Num(0), span.end)),
+                Error::InvalidString { span } => {
+                    Some((span.start, Token::Str(String::new()), span.end))
+                }
+                _ => None,
+            };
+
+            lex_errors.push(err);
+            placeholder
+        }
+    })
+}
+
+fn finish<T>(
+    result: std::result::Result<T, ParseError<Location, Token, Error>>,
+    errors: Vec<Error>,
+    lex_errors: Vec<Error>,
+) -> Result<T> {
+    match recover(result, errors, lex_errors) {
+        (Some(value), errors) if errors.is_empty() => Ok(value),
+        (_, errors) => Err(Error::from_errors(errors)),
+    }
+}
+
+/// Collects the errors of a parse, sorted by position, together with what
+/// the parser recovered, if it reached the end of the input.
+fn recover<T>(
+    result: std::result::Result<T, ParseError<Location, Token, Error>>,
+    mut errors: Vec<Error>,
+    lex_errors: Vec<Error>,
+) -> (Option<T>, Vec<Error>) {
+    errors.extend(lex_errors);
+    let value = match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            errors.push(err.into());
+            None
+        }
+    };
+
+    let mut errors: Vec<Error> = errors.into_iter().flat_map(Error::into_errors).collect();
+    errors.sort_by_key(|err| err.span().map(|span| span.start));
+    (value, errors)
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{lexer::*, mnemonic::*, register::*};
+    use crate::{expr::*, lexer::*, mnemonic::*, register::*, span::*};
 
     macro_rules! test_register {
         ($input: expr, $expected: expr) => {
             let lexer = Lexer::new($input);
-            let result = poco::RegisterParser::new().parse(lexer).unwrap();
+            let result = poco::RegisterParser::new()
+                .parse(&mut Vec::new(), lexer)
+                .unwrap();
 
             assert_eq!($expected, result);
         };
@@ -18,20 +172,18 @@ mod tests {
 
     macro_rules! test_item {
         ($input: expr, $expected: expr) => {
-            let lexer = Lexer::new($input);
-            let result = poco::ItemParser::new().parse(lexer).unwrap();
+            let result = parse_item($input).unwrap();
 
-            assert_eq!($expected, result);
+            assert_eq!($expected, result.node);
         };
     }
 
     macro_rules! test_file {
         ($input:expr, $expected: expr) => {
-            let lexer = Lexer::new($input);
-            let result = poco::FileParser::new().parse(lexer).unwrap();
+            let result = parse_file($input).unwrap();
 
             for (index, (left, right)) in $expected.iter().zip(result.iter()).enumerate() {
-                assert_eq!((index, left), (index, right));
+                assert_eq!((index, left), (index, &right.node));
             }
         };
     }
@@ -103,9 +255,10 @@ mod tests {
 
     #[test]
     fn instruction_i() {
-        // todo: Cannot parse -128.
-        // test_item!("LDI r0, #-128", Item::instr_i(OpcodeI::LDI, Register::R0, -128));
-
+        test_item!(
+            "LDI r0, #-128",
+            Item::instr_i(OpcodeI::LDI, Register::R0, -128)
+        );
         test_item!(
             "LDI r0, #-127",
             Item::instr_i(OpcodeI::LDI, Register::R0, -127)
@@ -126,6 +279,23 @@ mod tests {
             Item::instr_i(OpcodeI::ADDIU, Register::R0, 0)
         );
         test_item!("LDHI r0, #0", Item::instr_i(OpcodeI::LDHI, Register::R0, 0));
+
+        test_item!(
+            "LDIU r0, #255",
+            Item::instr_i(OpcodeI::LDIU, Register::R0, 255)
+        );
+        test_item!(
+            "LDI r0, #0x7f",
+            Item::instr_i(OpcodeI::LDI, Register::R0, 127)
+        );
+        test_item!(
+            "LDI r0, #-0b1",
+            Item::instr_i(OpcodeI::LDI, Register::R0, -1)
+        );
+        test_item!(
+            "LDIU r0, #'A'",
+            Item::instr_i(OpcodeI::LDIU, Register::R0, 65)
+        );
     }
 
     #[test]
@@ -163,11 +333,232 @@ mod tests {
         test_item!("JALR r0", Item::instr_jr(FunctJR::JALR, Register::R0));
     }
 
+    #[test]
+    fn pseudo_instruction() {
+        let pseudo = |input: &str| match parse_item(input).unwrap().node {
+            Item::Pseudo(pseudo) => pseudo,
+            item => panic!("expected a pseudo-instruction, got {:?}", item),
+        };
+
+        assert_eq!(
+            pseudo("LI r0, #0x1234"),
+            Pseudo::LI(Register::R0, Expr::Num(0x1234))
+        );
+        assert_eq!(
+            pseudo("LA r1, table"),
+            Pseudo::LA(Register::R1, "table".into())
+        );
+        assert_eq!(pseudo("CLR r2"), Pseudo::CLR(Register::R2));
+        assert_eq!(pseudo("NOT r3"), Pseudo::NOT(Register::R3, Register::R3));
+        assert_eq!(
+            pseudo("NOT r3, r4"),
+            Pseudo::NOT(Register::R3, Register::R4)
+        );
+        assert_eq!(pseudo("NEG r3"), Pseudo::NEG(Register::R3, Register::R3));
+        assert_eq!(
+            pseudo("NEG r3, r4"),
+            Pseudo::NEG(Register::R3, Register::R4)
+        );
+        assert_eq!(pseudo("INC r5"), Pseudo::INC(Register::R5));
+        assert_eq!(pseudo("DEC r5"), Pseudo::DEC(Register::R5));
+        assert_eq!(pseudo("B loop"), Pseudo::B("loop".into()));
+        assert_eq!(pseudo("CALL f"), Pseudo::CALL("f".into()));
+        assert_eq!(pseudo("RET"), Pseudo::RET);
+        assert_eq!(pseudo("PUSH r1"), Pseudo::PUSH(Register::R1));
+        assert_eq!(pseudo("POP r1"), Pseudo::POP(Register::R1));
+    }
+
+    #[test]
+    fn directive() {
+        test_item!(".equ IO, 2", Item::equ("IO".to_string(), Expr::Num(2)));
+        test_item!(".set IO, -0x1", Item::equ("IO".to_string(), Expr::Num(-1)));
+        test_item!(
+            ".equ COPY, IO",
+            Item::equ("COPY".to_string(), Expr::Symbol("IO".to_string()))
+        );
+        test_item!(
+            "LDIU r0, #IO",
+            Item::instr_i(OpcodeI::LDIU, Register::R0, Expr::Symbol("IO".to_string()))
+        );
+    }
+
+    #[test]
+    fn data_directive() {
+        let result = parse_file(
+            r#"
+.data
+:table .word 1, -2, end
+.byte 'A'
+.space 2 * 2
+.ascii "ab"
+.string "c\n"
+.text
+"#,
+        )
+        .unwrap();
+
+        let items: Vec<Item> = result.into_iter().map(|item| item.node).collect();
+        assert_eq!(
+            items,
+            vec![
+                Item::Directive(Directive::Section(Section::Data)),
+                Item::label("table".to_string()),
+                Item::Directive(Directive::Word(vec![
+                    Expr::Num(1),
+                    Expr::Num(-2),
+                    Expr::Symbol("end".to_string())
+                ])),
+                Item::Directive(Directive::Byte(vec![Expr::Num(65)])),
+                Item::Directive(Directive::Space(Expr::Num(4))),
+                Item::Directive(Directive::Ascii("ab".to_string())),
+                Item::Directive(Directive::String("c\n".to_string())),
+                Item::Directive(Directive::Section(Section::Text)),
+            ]
+        );
+    }
+
+    #[test]
+    fn placement_directive() {
+        let result = parse_file(".org 0x10\n.align VECTOR_SIZE * 2\n").unwrap();
+
+        let items: Vec<Item> = result.into_iter().map(|item| item.node).collect();
+        assert_eq!(
+            items,
+            vec![
+                Item::Directive(Directive::Org(Expr::Num(16))),
+                Item::Directive(Directive::Align(Expr::binary(
+                    BinaryOp::Mul,
+                    Expr::Symbol("VECTOR_SIZE".to_string()),
+                    Expr::Num(2)
+                ))),
+            ]
+        );
+    }
+
+    #[test]
+    fn expression() {
+        let immediate = |input: &str| match parse_item(input).unwrap().node {
+            Item::Mnemonic(Mnemonic::I(instr)) => instr.immediate,
+            item => panic!("expected an I instruction, got {:?}", item),
+        };
+        let symbol = |name: &str| Expr::Symbol(name.to_string());
+
+        assert_eq!(immediate("LDI r0, #1 + 2 * 3"), Expr::Num(7));
+        assert_eq!(immediate("LDI r0, #(1 + 2) * 3"), Expr::Num(9));
+        assert_eq!(immediate("LDI r0, #-(1 << 4 | 1)"), Expr::Num(-17));
+        assert_eq!(immediate("LDI r0, #~0 & 0xf ^ 3"), Expr::Num(12));
+        assert_eq!(immediate("LDI r0, #7 / 2 - 7 % 2 - 8 >> 1"), Expr::Num(-3));
+        assert_eq!(
+            immediate("LDIU r0, #hi(0x1234) + lo(0x1234)"),
+            Expr::Num(0x46)
+        );
+
+        assert_eq!(
+            immediate("LDIU r1, #lo(buffer + 4)"),
+            Expr::unary(
+                UnaryOp::Lo,
+                Expr::binary(BinaryOp::Add, symbol("buffer"), Expr::Num(4))
+            )
+        );
+        assert_eq!(
+            parse_item(".equ SIZE, 2 * COUNT").unwrap().node,
+            Item::equ(
+                "SIZE".to_string(),
+                Expr::binary(BinaryOp::Mul, Expr::Num(2), symbol("COUNT"))
+            )
+        );
+    }
+
+    #[test]
+    fn function_names() {
+        // `lo` and `hi` are functions only when called.
+        let input = "\
+:hi
+LDIU r0, #lo(hi)
+LDHI r1, #HI(Lo + 1)
+JMP Lo
+:Lo
+BNZ r0, hi
+";
+        let program = crate::assembler::assemble_items(parse_file(input).unwrap()).unwrap();
+        assert_eq!(program.labels["hi"], 0);
+        assert_eq!(program.labels["Lo"], 3);
+
+        match parse_file("LDIU r0, #low(label)\nLDI r0, #0x\n") {
+            Err(Error::Multiple(errors)) => {
+                assert!(matches!(
+                    &errors[0],
+                    Error::UnknownFunction { name, .. } if name == "low"
+                ));
+                assert!(matches!(errors[1], Error::InvalidNumber { .. }));
+            }
+            result => panic!("expected two errors, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn pseudo_names() {
+        // Pseudo-instruction names are free for labels and symbols.
+        let input = "\
+.equ inc, 1
+:b :call
+CALL ret
+B not
+:ret
+INC r0
+LI r1, #inc
+:not
+RET
+";
+        let items: Vec<Item> = parse_file(input)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.node)
+            .collect();
+        assert_eq!(
+            items[1..6],
+            [
+                Item::Label("b".to_string()),
+                Item::Label("call".to_string()),
+                Item::Pseudo(Pseudo::CALL("ret".to_string())),
+                Item::Pseudo(Pseudo::B("not".to_string())),
+                Item::Label("ret".to_string()),
+            ]
+        );
+
+        let program = crate::assembler::assemble_items(parse_file(input).unwrap()).unwrap();
+        assert_eq!(program.labels["call"], 0);
+        assert!(program.labels.contains_key("not"));
+    }
+
     #[test]
     fn label() {
         test_item!(":label", Item::Label("label".to_string()));
         test_item!(":label0", Item::Label("label0".to_string()));
         test_item!(":end", Item::Label("end".to_string()));
+        test_item!(":.loop", Item::Label(".loop".to_string()));
+        test_item!(":1", Item::Label("1".to_string()));
+        test_item!(
+            "BNZ r0, 1b",
+            Item::instr_b(OpcodeB::BNZ, Register::R0, "1b".to_string())
+        );
+    }
+
+    #[test]
+    fn label_like_keyword() {
+        // Only a keyword's own case is read where a label stands.
+        let items = parse_file(":add\nJMP add\n:and BNZ r0, and\n").unwrap();
+        let nodes: Vec<&Item> = items.iter().map(|item| &item.node).collect();
+        assert_eq!(
+            nodes,
+            vec![
+                &Item::Label("add".to_string()),
+                &Item::instr_j(OpcodeJ::JMP, "add".to_string()),
+                &Item::Label("and".to_string()),
+                &Item::instr_b(OpcodeB::BNZ, Register::R0, "and".to_string()),
+            ]
+        );
+        assert!(crate::assembler::assemble_items(items).is_ok());
     }
 
     #[test]
@@ -189,14 +580,162 @@ JMP end
             Item::instr_r(FunctR::LD, Register::R1, Register::R2),
             Item::instr_b(OpcodeB::BEZ, Register::R0, "jump1".into()),
             Item::label("jump1".to_string()),
-            Item::instr_i(OpcodeI::LDI, Register::R1, 1.into()),
+            Item::instr_i(OpcodeI::LDI, Register::R1, 1),
             Item::label("jump2".to_string()),
             Item::instr_r(FunctR::ST, Register::R1, Register::R2),
             Item::instr_r(FunctR::ADD, Register::R0, Register::R1),
-            Item::instr_i(OpcodeI::ADDI, Register::R0, 1.into()),
+            Item::instr_i(OpcodeI::ADDI, Register::R0, 1),
             Item::instr_j(OpcodeJ::JMP, "end".into()),
             Item::label("end".to_string()),
         ];
         test_file!(input1, expected1);
     }
+
+    #[test]
+    fn error() {
+        match parse_item("LDI r0, #0x") {
+            Err(Error::InvalidNumber { .. }) => {}
+            result => panic!("expected an invalid number error, got {:?}", result),
+        }
+
+        match parse_item("ADD r0 r1") {
+            Err(Error::UnrecognizedToken { token, .. }) => assert_eq!(token, Token::R1),
+            result => panic!("expected an unrecognized token error, got {:?}", result),
+        }
+
+        match parse_item("ADD r0,") {
+            Err(Error::UnrecognizedToken { token, .. }) => assert_eq!(token, Token::Newline),
+            result => panic!("expected an unrecognized token error, got {:?}", result),
+        }
+
+        match parse_file("ADD r0, r1 @") {
+            Err(Error::InvalidToken { .. }) => {}
+            result => panic!("expected an invalid token error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn strict_case() {
+        let strict = ParseOptions { strict_case: true };
+
+        assert_eq!(
+            parse_item("add R0, r1").unwrap().node,
+            Item::instr_r(FunctR::ADD, Register::R0, Register::R1)
+        );
+        assert_eq!(
+            parse_item_with("ADD r0, r1", strict).unwrap().node,
+            Item::instr_r(FunctR::ADD, Register::R0, Register::R1)
+        );
+
+        match parse_item_with("add r0, r1", strict) {
+            Err(Error::UnrecognizedToken { token, .. }) => {
+                assert_eq!(token, Token::Ident("add".to_string()))
+            }
+            result => panic!("expected an unrecognized token error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn multiple_errors() {
+        let input = r"
+ADD r0 r1
+SUB r0, r1
+LDI r0, #0xg
+JMP @ end
+MV r0,
+LDI r1, #300
+";
+        let errors = assemble_errors(input);
+
+        let lines: Vec<u32> = errors
+            .iter()
+            .map(|err| err.span().unwrap().start.line)
+            .collect();
+        assert_eq!(lines, vec![2, 4, 5, 5, 6, 7]);
+
+        assert!(matches!(errors[0], Error::UnrecognizedToken { .. }));
+        assert!(matches!(errors[1], Error::InvalidNumber { .. }));
+        assert!(matches!(&errors[2], Error::UndefinedLabel { label, .. } if label == "end"));
+        assert!(matches!(errors[3], Error::InvalidToken { .. }));
+        assert!(matches!(
+            errors[4],
+            Error::UnrecognizedToken {
+                token: Token::Newline,
+                ..
+            }
+        ));
+        // The assembler still checks the lines that could be read.
+        assert!(matches!(errors[5], Error::ImmediateOutOfRange { .. }));
+    }
+
+    fn assemble_errors(input: &str) -> Vec<Error> {
+        let mut sources = crate::source::SourceMap::new();
+        let file = sources.add("main.asm", input, None);
+        let (items, errors) = parse_source_recovered(
+            &mut sources,
+            file,
+            &Environment::default(),
+            ParseOptions::default(),
+        );
+
+        crate::assembler::assemble_recovered(items, errors, Default::default())
+            .unwrap_err()
+            .into_errors()
+    }
+
+    #[test]
+    fn errors_of_both_passes() {
+        let input = "ADD r0 r1\nLDI r0, #300\nJMP nowhere\n:loop SUB r0\nJMP loop\n";
+        let errors = assemble_errors(input);
+
+        let lines: Vec<u32> = errors
+            .iter()
+            .map(|err| err.span().unwrap().start.line)
+            .collect();
+        assert_eq!(lines, vec![1, 2, 3, 4]);
+
+        assert!(matches!(errors[0], Error::UnrecognizedToken { .. }));
+        assert!(matches!(errors[1], Error::ImmediateOutOfRange { .. }));
+        assert!(matches!(&errors[2], Error::UndefinedLabel { label, .. } if label == "nowhere"));
+        assert!(matches!(errors[3], Error::UnrecognizedToken { .. }));
+    }
+
+    #[test]
+    fn one_instruction_per_line() {
+        let input = "ADD r0, r1 SUB r2, r3\nNOP\n:a :d NOP\nNOP :c\n";
+        let errors = parse_file(input).unwrap_err().into_errors();
+
+        let tokens: Vec<(u32, &Token)> = errors
+            .iter()
+            .map(|err| match err {
+                Error::UnrecognizedToken {
+                    token,
+                    span,
+                    expected,
+                } => {
+                    assert!(expected.contains(&r#""Newline""#.to_string()));
+                    (span.start.line, token)
+                }
+                err => panic!("expected an unrecognized token error, got {:?}", err),
+            })
+            .collect();
+        assert_eq!(tokens, vec![(1, &Token::SUB), (4, &Token::Colon)]);
+
+        assert_eq!(parse_file("\n\n:a :d NOP\n\n").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn span() {
+        let result = parse_file(":loop\n  ADD r0, r1\nBNZ r2, loop").unwrap();
+
+        let spans: Vec<Span> = result.iter().map(|item| item.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(Location::new(0, 1, 1), Location::new(5, 1, 6)),
+                Span::new(Location::new(8, 2, 3), Location::new(18, 2, 13)),
+                Span::new(Location::new(19, 3, 1), Location::new(31, 3, 13)),
+            ]
+        );
+    }
 }
@@ -0,0 +1,1010 @@
+use crate::{
+    error::{Error, Result},
+    parser::{poco, tokens, ParseOptions},
+    source::{Origin, SourceMap},
+    span::{FileId, Location, Span},
+    token::Token,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    vec,
+};
+
+/// How deeply macro calls may be nested.
+pub const MAX_DEPTH: usize = 64;
+
+type SpannedToken = (Location, Token, Location);
+
+/// What the source can refer to besides itself.
+#[derive(Debug, Default, Clone)]
+pub struct Environment {
+    /// The directories searched for included files, after the directory of
+    /// the including file.
+    pub include_paths: Vec<PathBuf>,
+
+    /// Symbols defined before the first line, as a name and the text of its
+    /// value: `-D DEBUG=1`
+    pub defines: Vec<(String, String)>,
+}
+
+/// Turns the text of a file into the tokens the parser reads, reading the
+/// files named by `.include` and expanding macros.
+///
+/// An included file is read in place of its `.include` directive, which is
+/// passed on to the parser in front of the included tokens.
+///
+/// A macro is defined with `.macro NAME param, ...` and `.endm`, and called
+/// like an instruction: `NAME arg, ...`. In the body, each parameter is
+/// replaced by its argument, and `%%name` by a label unique to the
+/// expansion. Each expansion is added to the `SourceMap` with the call site
+/// as its origin, so that diagnostics show both the body and the call.
+///
+/// The lines between `.if expr`, `.ifdef NAME` or `.ifndef NAME` and the
+/// matching `.else` or `.endif` are only passed on when the condition holds.
+/// Conditions are evaluated with the symbols defined so far by `.equ`,
+/// `.set` and the `defines` of the `Environment`, since labels have no
+/// address yet.
+pub struct Preprocessor<'a> {
+    sources: &'a mut SourceMap,
+    include_paths: &'a [PathBuf],
+    options: ParseOptions,
+    errors: &'a mut Vec<Error>,
+
+    /// The token streams being read, innermost last.
+    frames: Vec<Frame>,
+    macros: HashMap<String, Macro>,
+
+    /// The symbols defined so far, with `None` for a value that depends on
+    /// a label.
+    symbols: HashMap<String, Option<i64>>,
+
+    /// The open conditional blocks, innermost last.
+    conditions: Vec<Condition>,
+
+    /// The number of expansions so far, used to name local labels.
+    expansions: usize,
+    line: LineState,
+}
+
+struct Frame {
+    tokens: vec::IntoIter<SpannedToken>,
+
+    /// The canonical path of the file being read, to detect include cycles.
+    path: Option<PathBuf>,
+
+    /// Whether the frame is the body of a macro call.
+    expansion: bool,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<SpannedToken>,
+
+    /// The span of the name in the definition.
+    span: Span,
+}
+
+struct Condition {
+    /// The `.if`, `.ifdef` or `.ifndef` that opened the block.
+    directive: Token,
+    span: Span,
+
+    /// The number of frames when the block was opened; it must be closed in
+    /// the same file or macro.
+    frame: usize,
+
+    /// Whether the enclosing block is assembled.
+    outer: bool,
+
+    /// Whether a branch of the block has been assembled.
+    taken: bool,
+
+    /// Whether the current branch is assembled.
+    active: bool,
+
+    /// Whether the `.else` has been read.
+    in_else: bool,
+}
+
+/// Where the next token is in its line.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum LineState {
+    /// At the start of a statement, possibly after labels.
+    Start,
+
+    /// After the `:` of a label.
+    Label,
+
+    /// Inside a statement.
+    Rest,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(
+        sources: &'a mut SourceMap,
+        file: FileId,
+        environment: &'a Environment,
+        options: ParseOptions,
+        errors: &'a mut Vec<Error>,
+    ) -> Self {
+        let mut preprocessor = Preprocessor {
+            sources,
+            include_paths: &environment.include_paths,
+            options,
+            errors,
+            frames: Vec::new(),
+            macros: HashMap::new(),
+            symbols: HashMap::new(),
+            conditions: Vec::new(),
+            expansions: 0,
+            line: LineState::Start,
+        };
+
+        let path = preprocessor
+            .sources
+            .get(file)
+            .map(|source| canonical(Path::new(&source.name)));
+        preprocessor.push_file(file, path);
+
+        // The definitions are read first, as if written before the first line.
+        if !environment.defines.is_empty() {
+            let text: String = environment
+                .defines
+                .iter()
+                .map(|(name, value)| format!(".equ {}, {}\n", name, value))
+                .collect();
+            let defines = preprocessor.sources.add("<command line>", text, None);
+            preprocessor.push_file(defines, None);
+        }
+
+        preprocessor
+    }
+
+    fn push_file(&mut self, file: FileId, path: Option<PathBuf>) {
+        let source = match self.sources.get(file) {
+            Some(source) => source.source.clone(),
+            None => return,
+        };
+        let tokens: Vec<_> = tokens(&source, file, self.options, self.errors).collect();
+
+        self.frames.push(Frame {
+            tokens: tokens.into_iter(),
+            path,
+            expansion: false,
+        });
+    }
+
+    /// Passes `tokens` to the parser before the rest of the current frame.
+    fn push_tokens(&mut self, tokens: Vec<SpannedToken>, expansion: bool) {
+        self.frames.push(Frame {
+            tokens: tokens.into_iter(),
+            path: None,
+            expansion,
+        });
+    }
+
+    /// Returns the next token of any frame.
+    fn next_token(&mut self) -> Option<SpannedToken> {
+        loop {
+            let frame = self.frames.last_mut()?;
+            match frame.tokens.next() {
+                Some(token) => return Some(token),
+                None => {
+                    self.frames.pop();
+                    self.close_conditions();
+                }
+            }
+        }
+    }
+
+    /// Reports the conditional blocks left open in a frame that has ended.
+    fn close_conditions(&mut self) {
+        while let Some(condition) = self.conditions.last() {
+            if condition.frame <= self.frames.len() {
+                break;
+            }
+
+            self.errors.push(Error::UnmatchedDirective {
+                directive: condition.directive.clone(),
+                span: condition.span,
+            });
+            self.conditions.pop();
+        }
+    }
+
+    /// Whether the current line is assembled.
+    fn active(&self) -> bool {
+        match self.conditions.last() {
+            Some(condition) => condition.active,
+            None => true,
+        }
+    }
+
+    /// Opens a conditional block with `.if`, `.ifdef` or `.ifndef`.
+    fn open_condition(&mut self, directive: SpannedToken) {
+        let (line, newline) = self.read_line();
+        let span = Span::new(directive.0, directive.2);
+
+        // The conditions of a block that is not assembled are not evaluated.
+        let outer = self.active();
+        let value = outer && self.evaluate_condition(&directive, line, newline);
+
+        self.conditions.push(Condition {
+            directive: directive.1,
+            span,
+            frame: self.frames.len(),
+            outer,
+            taken: value,
+            active: value,
+            in_else: false,
+        });
+    }
+
+    fn evaluate_condition(
+        &mut self,
+        directive: &SpannedToken,
+        line: Vec<SpannedToken>,
+        newline: Option<SpannedToken>,
+    ) -> bool {
+        let result = match directive.1 {
+            Token::If => self
+                .evaluate(line, newline, directive.2)
+                .map(|value| value != 0),
+            _ => match line.as_slice() {
+                [(_, Token::Ident(name), _)] => {
+                    Ok(self.symbols.contains_key(name) == (directive.1 == Token::Ifdef))
+                }
+                _ => {
+                    let (l, token, r) = line.into_iter().next().or(newline).unwrap_or((
+                        directive.2,
+                        Token::Newline,
+                        directive.2,
+                    ));
+                    Err(Error::UnrecognizedToken {
+                        token,
+                        span: Span::new(l, r),
+                        expected: vec![r#""Ident""#.to_string()],
+                    })
+                }
+            },
+        };
+
+        result.unwrap_or_else(|err| {
+            self.errors.push(err);
+            false
+        })
+    }
+
+    /// Switches a conditional block to its `.else` branch, or closes it
+    /// with `.endif`.
+    fn continue_condition(&mut self, directive: SpannedToken) {
+        let (line, _) = self.read_line();
+        if let Some((l, token, r)) = line.into_iter().next() {
+            self.errors.push(Error::UnrecognizedToken {
+                token,
+                span: Span::new(l, r),
+                expected: vec![r#""Newline""#.to_string()],
+            });
+        }
+
+        let frame = self.frames.len();
+        match self.conditions.last_mut() {
+            Some(condition) if condition.frame == frame && directive.1 == Token::Endif => {
+                self.conditions.pop();
+            }
+            Some(condition) if condition.frame == frame && !condition.in_else => {
+                condition.in_else = true;
+                condition.active = condition.outer && !condition.taken;
+                condition.taken = true;
+            }
+            _ => self.errors.push(Error::UnmatchedDirective {
+                directive: directive.1,
+                span: Span::new(directive.0, directive.2),
+            }),
+        }
+    }
+
+    /// Records the symbol defined by `.equ NAME, value` or `.set`, passing
+    /// the line on to the parser.
+    fn define_symbol(&mut self) {
+        let (line, newline) = self.read_line();
+
+        if let [(_, Token::Ident(name), _), (_, Token::Comma, end), value @ ..] = line.as_slice() {
+            // The parser reports a malformed value.
+            let value = self.evaluate(value.to_vec(), newline.clone(), *end).ok();
+            self.symbols.insert(name.clone(), value);
+        }
+
+        let mut rest = line;
+        rest.extend(newline);
+        self.push_tokens(rest, false);
+    }
+
+    /// Evaluates the expression `tokens` that follows `start`, with the
+    /// symbols defined so far.
+    fn evaluate(
+        &self,
+        mut tokens: Vec<SpannedToken>,
+        newline: Option<SpannedToken>,
+        start: Location,
+    ) -> Result<i64> {
+        let end = tokens.last().map_or(start, |token| token.2);
+        let span = Span::new(tokens.first().map_or(start, |token| token.0), end);
+        tokens.push(newline.unwrap_or((end, Token::Newline, end)));
+
+        let expr = poco::LineExprParser::new()
+            .parse(&mut Vec::new(), tokens)
+            .map_err(Error::from)?;
+        expr.eval(&|name| self.symbols.get(name).copied().flatten(), span)
+    }
+
+    /// Returns the rest of the line in the current frame, and its newline.
+    fn read_line(&mut self) -> (Vec<SpannedToken>, Option<SpannedToken>) {
+        let mut line = Vec::new();
+        if let Some(frame) = self.frames.last_mut() {
+            for token in &mut frame.tokens {
+                if token.1 == Token::Newline {
+                    return (line, Some(token));
+                }
+                line.push(token);
+            }
+        }
+
+        (line, None)
+    }
+
+    /// Reads `.macro NAME param, ...` up to its `.endm` and defines the macro.
+    fn define(&mut self, directive: SpannedToken) {
+        let (header, _) = self.read_line();
+        let header = self.macro_header(&directive, header);
+
+        // Read the body even if the header is wrong, so that it is not
+        // assembled.
+        let mut body = Vec::new();
+        let mut depth = 0;
+        let mut at_start = true;
+        loop {
+            let token = match self.frames.last_mut().and_then(|frame| frame.tokens.next()) {
+                Some(token) => token,
+                None => {
+                    self.errors.push(Error::UnmatchedDirective {
+                        directive: Token::Macro,
+                        span: Span::new(directive.0, directive.2),
+                    });
+                    return;
+                }
+            };
+
+            match token.1 {
+                Token::Macro if at_start => depth += 1,
+                Token::Endm if at_start && depth == 0 => {
+                    self.read_line();
+                    break;
+                }
+                Token::Endm if at_start => depth -= 1,
+                _ => {}
+            }
+            at_start = token.1 == Token::Newline;
+            body.push(token);
+        }
+
+        if let Some((name, span, params)) = header {
+            if let Some(previous) = self.macros.get(&name) {
+                self.errors.push(Error::DuplicateMacro {
+                    name,
+                    span,
+                    previous: previous.span,
+                });
+                return;
+            }
+
+            self.macros.insert(name, Macro { params, body, span });
+        }
+    }
+
+    /// Parses `NAME param, ...` after `.macro`.
+    fn macro_header(
+        &mut self,
+        directive: &SpannedToken,
+        header: Vec<SpannedToken>,
+    ) -> Option<(String, Span, Vec<String>)> {
+        let mut header = header.into_iter();
+        let (name, span) = match header.next() {
+            Some((l, Token::Ident(name), r)) => (name, Span::new(l, r)),
+            token => {
+                let (l, token, r) = token.unwrap_or((directive.2, Token::Newline, directive.2));
+                self.errors.push(Error::UnrecognizedToken {
+                    token,
+                    span: Span::new(l, r),
+                    expected: vec![r#""Ident""#.to_string()],
+                });
+                return None;
+            }
+        };
+
+        let mut params = Vec::new();
+        let mut expect_param = true;
+        let mut end = span.end;
+        for (l, token, r) in header {
+            end = r;
+            match token {
+                Token::Ident(param) if expect_param => params.push(param),
+                Token::Comma if !expect_param => {}
+                token => {
+                    let expected = if expect_param { r#""Ident""# } else { r#"",""# };
+                    self.errors.push(Error::UnrecognizedToken {
+                        token,
+                        span: Span::new(l, r),
+                        expected: vec![expected.to_string()],
+                    });
+                    return None;
+                }
+            }
+            expect_param = !expect_param;
+        }
+
+        if expect_param && !params.is_empty() {
+            self.errors.push(Error::UnrecognizedToken {
+                token: Token::Newline,
+                span: Span::new(end, end),
+                expected: vec![r#""Ident""#.to_string()],
+            });
+            return None;
+        }
+
+        Some((name, span, params))
+    }
+
+    /// Expands a call of the macro `name`, which is the first token of
+    /// `call`.
+    fn call(&mut self, name: String, call: SpannedToken) {
+        let (args, newline) = self.read_line();
+        let end = args.last().map_or(call.2, |token| token.2);
+        let call_site = Span::new(call.0, end);
+        let args = split_args(args);
+
+        let depth = self.frames.iter().filter(|frame| frame.expansion).count();
+        let definition = &self.macros[&name];
+        let err = if args.len() != definition.params.len() {
+            Some(Error::MacroArguments {
+                expected: definition.params.len(),
+                found: args.len(),
+                name: name.clone(),
+                span: call_site,
+            })
+        } else if depth >= MAX_DEPTH {
+            Some(Error::MacroRecursion {
+                name: name.clone(),
+                span: call_site,
+            })
+        } else {
+            None
+        };
+        if let Some(err) = err {
+            // Keep the newline so that the labels before the call end there.
+            self.errors.push(err);
+            self.push_tokens(newline.into_iter().collect(), false);
+            return;
+        }
+
+        self.expansions += 1;
+        let file = self
+            .sources
+            .expand(definition.span.start.file, &name, call_site);
+        let mut tokens = expand(definition, &args, file, self.expansions);
+        tokens.extend(newline);
+
+        self.push_tokens(tokens, true);
+    }
+
+    /// Reads the file named by an `.include`, returning the rest of the
+    /// directive's line for the parser.
+    fn include(&mut self, directive: &SpannedToken) {
+        let (line, newline) = self.read_line();
+
+        if let [(_, Token::Str(name), end)] = line.as_slice() {
+            let span = Span::new(directive.0, *end);
+            self.include_file(name, span);
+        }
+
+        // The parser sees the directive, and reports it if it is malformed.
+        let mut rest = line;
+        rest.extend(newline);
+        self.push_tokens(rest, false);
+    }
+
+    fn include_file(&mut self, name: &str, span: Span) {
+        let from = self
+            .sources
+            .get(span.start.file)
+            .map_or_else(PathBuf::new, |source| PathBuf::from(&source.name));
+        let path = match self.resolve(&from, name) {
+            Some(path) => path,
+            None => {
+                self.errors.push(Error::IncludeNotFound {
+                    path: name.to_string(),
+                    span,
+                });
+                return;
+            }
+        };
+
+        let canonical = canonical(&path);
+        if self
+            .frames
+            .iter()
+            .any(|frame| frame.path.as_ref() == Some(&canonical))
+        {
+            self.errors.push(Error::IncludeCycle {
+                path: name.to_string(),
+                span,
+            });
+            return;
+        }
+
+        match fs::read_to_string(&path) {
+            Ok(source) => {
+                let file = self.sources.add(
+                    path.display().to_string(),
+                    source,
+                    Some(Origin::Include(span)),
+                );
+                self.push_file(file, Some(canonical));
+            }
+            Err(err) => self.errors.push(err.into()),
+        }
+    }
+
+    /// Looks `name` up next to the file at `from`, then in the include paths.
+    fn resolve(&self, from: &Path, name: &str) -> Option<PathBuf> {
+        let dir = from.parent().unwrap_or_else(|| Path::new(""));
+
+        std::iter::once(dir)
+            .chain(self.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+}
+
+impl<'a> Iterator for Preprocessor<'a> {
+    type Item = SpannedToken;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let token = self.next_token()?;
+
+            if self.line == LineState::Start {
+                match token.1 {
+                    Token::If | Token::Ifdef | Token::Ifndef => {
+                        self.open_condition(token);
+                        continue;
+                    }
+                    Token::Else | Token::Endif => {
+                        self.continue_condition(token);
+                        continue;
+                    }
+                    _ if !self.active() => {
+                        if token.1 != Token::Newline {
+                            self.read_line();
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            match (self.line, &token.1) {
+                (_, Token::Newline) => self.line = LineState::Start,
+                (LineState::Start, Token::Colon) => self.line = LineState::Label,
+                (LineState::Label, _) => self.line = LineState::Start,
+                (LineState::Start, Token::Macro) => {
+                    self.define(token);
+                    continue;
+                }
+                (LineState::Start, Token::Endm) => {
+                    self.errors.push(Error::UnmatchedDirective {
+                        directive: Token::Endm,
+                        span: Span::new(token.0, token.2),
+                    });
+                    self.read_line();
+                    continue;
+                }
+                (LineState::Start, Token::Include) => {
+                    self.line = LineState::Rest;
+                    self.include(&token);
+                }
+                (LineState::Start, Token::Equ) | (LineState::Start, Token::Set) => {
+                    self.line = LineState::Rest;
+                    self.define_symbol();
+                }
+                (LineState::Start, Token::Ident(name)) if self.macros.contains_key(name) => {
+                    let name = name.clone();
+                    self.call(name, token);
+                    continue;
+                }
+                _ => self.line = LineState::Rest,
+            }
+
+            return Some(token);
+        }
+    }
+}
+
+/// Splits the arguments of a macro call at the commas outside parentheses.
+fn split_args(tokens: Vec<SpannedToken>) -> Vec<Vec<SpannedToken>> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut args = vec![Vec::new()];
+    let mut depth = 0usize;
+    for token in tokens {
+        match token.1 {
+            Token::OpenParen => depth += 1,
+            Token::CloseParen => depth = depth.saturating_sub(1),
+            Token::Comma if depth == 0 => {
+                args.push(Vec::new());
+                continue;
+            }
+            _ => {}
+        }
+        args.last_mut().unwrap().push(token);
+    }
+
+    args
+}
+
+/// Returns the body of `definition` with its parameters replaced by `args`
+/// and its locations moved to the expansion `file`.
+fn expand(
+    definition: &Macro,
+    args: &[Vec<SpannedToken>],
+    file: FileId,
+    expansion: usize,
+) -> Vec<SpannedToken> {
+    let relocate = |mut location: Location| {
+        location.file = file;
+        location
+    };
+
+    let body = &definition.body;
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        let (l, token, r) = &body[i];
+
+        // `%%name`, written without spaces, is a label local to the expansion.
+        if let (
+            Token::Percent,
+            Some((l2, Token::Percent, r2)),
+            Some((l3, Token::Ident(name), r3)),
+        ) = (token, body.get(i + 1), body.get(i + 2))
+        {
+            if l2 == r && l3 == r2 {
+                let local = format!("{}@{}", name, expansion);
+                tokens.push((relocate(*l), Token::Ident(local), relocate(*r3)));
+                i += 3;
+                continue;
+            }
+        }
+
+        match token {
+            Token::Ident(name) => match definition.params.iter().position(|p| p == name) {
+                Some(index) => tokens.extend(args[index].iter().cloned()),
+                None => tokens.push((relocate(*l), token.clone(), relocate(*r))),
+            },
+            _ => tokens.push((relocate(*l), token.clone(), relocate(*r))),
+        }
+        i += 1;
+    }
+
+    tokens
+}
+
+/// Returns the canonical form of `path`, so that one file reached through
+/// different paths is recognized.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{expr::*, mnemonic::*, parser::*, register::*};
+
+    fn nodes(input: &str) -> Vec<Item> {
+        parse_file(input)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.node)
+            .collect()
+    }
+
+    #[test]
+    fn expand() {
+        let items = nodes(
+            r#"
+.macro spill reg
+    ADDI r7, #-1
+    ST reg, (r7)
+.endm
+:save spill r1
+spill r2
+"#,
+        );
+
+        assert_eq!(
+            items,
+            vec![
+                Item::label("save".into()),
+                Item::instr_i(OpcodeI::ADDI, Register::R7, -1),
+                Item::instr_r(FunctR::ST, Register::R1, Register::R7),
+                Item::instr_i(OpcodeI::ADDI, Register::R7, -1),
+                Item::instr_r(FunctR::ST, Register::R2, Register::R7),
+            ]
+        );
+    }
+
+    #[test]
+    fn local_label() {
+        let items = nodes(
+            r#"
+.macro wait reg, n
+    LDI reg, #n
+:%%loop
+    ADDI reg, #-1
+    BNZ reg, %%loop
+.endm
+wait r0, 3
+wait r1, (1 + 2)
+"#,
+        );
+
+        let labels: Vec<&str> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Label(label) => Some(label.as_str()),
+                Item::Mnemonic(Mnemonic::B(instr)) => Some(instr.label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, vec!["loop@1", "loop@1", "loop@2", "loop@2"]);
+        assert_eq!(items[4], Item::instr_i(OpcodeI::LDI, Register::R1, 3));
+    }
+
+    #[test]
+    fn nested() {
+        let items = nodes(
+            r#"
+.macro twice op
+    op
+    op
+.endm
+.macro outer
+    .macro inner
+        NOP
+    .endm
+    twice inner
+.endm
+outer
+"#,
+        );
+
+        assert_eq!(
+            items,
+            vec![Item::instr_r(FunctR::NOP, Register::R0, Register::R0); 2]
+        );
+    }
+
+    #[test]
+    fn expansion_spans() {
+        let mut sources = crate::source::SourceMap::new();
+        let file = sources.add("main.asm", ".macro m\nNOP\n.endm\nm\n", None);
+        let items = parse_source(
+            &mut sources,
+            file,
+            &Environment::default(),
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        let span = items[0].span;
+        assert_eq!(span.start.line, 2);
+        assert_eq!(span.start.file, FileId(1));
+        match &sources.get(FileId(1)).unwrap().origin {
+            Some(crate::source::Origin::Macro { name, call_site }) => {
+                assert_eq!(name, "m");
+                assert_eq!(call_site.start.line, 4);
+            }
+            origin => panic!("expected a macro expansion, got {:?}", origin),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let errors = parse_file(
+            r#"
+.macro m a
+.endm
+.macro m c
+.endm
+m
+m 1, 2
+.endm
+.macro r
+r
+.endm
+r
+.macro open
+"#,
+        )
+        .unwrap_err()
+        .into_errors();
+
+        assert!(matches!(&errors[0], Error::DuplicateMacro { name, .. } if name == "m"));
+        assert!(matches!(
+            errors[1],
+            Error::MacroArguments {
+                expected: 1,
+                found: 0,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[2],
+            Error::MacroArguments {
+                expected: 1,
+                found: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[3],
+            Error::UnmatchedDirective {
+                directive: crate::token::Token::Endm,
+                ..
+            }
+        ));
+        assert!(matches!(&errors[4], Error::MacroRecursion { name, .. } if name == "r"));
+        assert!(matches!(
+            errors[5],
+            Error::UnmatchedDirective {
+                directive: crate::token::Token::Macro,
+                ..
+            }
+        ));
+        assert_eq!(errors.len(), 6);
+    }
+
+    #[test]
+    fn condition() {
+        let items = nodes(
+            r#"
+.equ DEBUG, 1
+.equ LEVEL, DEBUG + 1
+.if LEVEL - 1
+    LDI r0, #1
+.else
+    LDI r0, #2
+.endif
+.ifdef DEBUG
+    .ifndef DEBUG
+        LDI r0, #3
+    .else
+        LDI r0, #4
+    .endif
+.endif
+.if 0
+    .if 1 / 0
+        LDI r0, #5
+    .endif
+.else
+    LDI r0, #6
+.endif
+"#,
+        );
+
+        assert_eq!(
+            items,
+            vec![
+                Item::equ("DEBUG".into(), Expr::Num(1)),
+                Item::equ(
+                    "LEVEL".into(),
+                    Expr::binary(BinaryOp::Add, Expr::Symbol("DEBUG".into()), Expr::Num(1))
+                ),
+                Item::instr_i(OpcodeI::LDI, Register::R0, 1),
+                Item::instr_i(OpcodeI::LDI, Register::R0, 4),
+                Item::instr_i(OpcodeI::LDI, Register::R0, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn condition_in_macro() {
+        let items = nodes(
+            r#"
+.macro load reg, n
+    .if n
+        LDI reg, #n
+    .else
+        SUB reg, reg
+    .endif
+.endm
+load r0, 0
+load r1, 2
+"#,
+        );
+
+        assert_eq!(
+            items,
+            vec![
+                Item::instr_r(FunctR::SUB, Register::R0, Register::R0),
+                Item::instr_i(OpcodeI::LDI, Register::R1, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn condition_errors() {
+        let errors = parse_file(
+            r#"
+:start
+.if start
+.endif
+.ifdef
+.endif
+.else
+.macro m
+    .if 1
+.endm
+m
+.if 1
+.else
+.else
+"#,
+        )
+        .unwrap_err()
+        .into_errors();
+
+        assert!(matches!(&errors[0], Error::UndefinedSymbol { symbol, .. } if symbol == "start"));
+        assert!(matches!(
+            errors[1],
+            Error::UnrecognizedToken {
+                token: Token::Newline,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[2],
+            Error::UnmatchedDirective {
+                directive: Token::Else,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[3],
+            Error::UnmatchedDirective {
+                directive: Token::If,
+                ..
+            }
+        ));
+        assert_eq!(errors[3].span().unwrap().start.line, 9);
+        assert!(matches!(
+            errors[4],
+            Error::UnmatchedDirective {
+                directive: Token::If,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[5],
+            Error::UnmatchedDirective {
+                directive: Token::Else,
+                ..
+            }
+        ));
+        assert_eq!(errors.len(), 6);
+    }
+}
@@ -13,6 +13,22 @@ pub enum Register {
 }
 
 impl Register {
+    pub const ALL: [Register; 8] = [
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+    ];
+
+    /// Returns the register with the 3-bit number `id`.
+    pub fn from_id(id: u16) -> Option<Self> {
+        Register::ALL.get(usize::from(id)).copied()
+    }
+
     pub fn id(&self) -> u16 {
         match &self {
             Register::R0 => 0,
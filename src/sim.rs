@@ -0,0 +1,716 @@
+use crate::{
+    code::Program,
+    error::{Error, Result},
+    mnemonic::*,
+    register::Register,
+};
+use std::collections::VecDeque;
+
+/// Options that set up a `Machine`.
+#[derive(Debug, Clone, Copy)]
+pub struct MachineOptions {
+    /// The number of words of the instruction memory. Defaults to the whole
+    /// 16-bit address space.
+    pub imem_size: usize,
+
+    /// The number of words of the data memory. Defaults to the whole 16-bit
+    /// address space.
+    pub dmem_size: usize,
+
+    /// The number of instructions the machine remembers, and so can undo.
+    /// Defaults to 0, which records nothing.
+    pub history: usize,
+}
+
+impl Default for MachineOptions {
+    fn default() -> Self {
+        MachineOptions {
+            imem_size: 1 << 16,
+            dmem_size: 1 << 16,
+            history: 0,
+        }
+    }
+}
+
+/// A Poco processor that runs one instruction per cycle.
+///
+/// The registers and the memories start zeroed, and the program starts at
+/// address 0.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Machine {
+    registers: [u16; 8],
+    pc: u16,
+    imem: Vec<u16>,
+    dmem: Vec<u16>,
+    cycles: u64,
+
+    /// The last instructions run, oldest first, at most `capacity` of them.
+    history: VecDeque<Record>,
+    capacity: usize,
+}
+
+/// What one instruction did.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Step {
+    /// The address of the instruction.
+    pub pc: u16,
+    pub code: u16,
+
+    /// The address of the instruction that follows.
+    pub next_pc: u16,
+
+    /// The register or memory word the instruction wrote, if any. No Poco
+    /// instruction writes more than one.
+    pub write: Option<Write>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Write {
+    Register(Register, u16),
+
+    /// A word of the data memory: `(address, value)`
+    Memory(u16, u16),
+}
+
+impl Write {
+    pub fn target(&self) -> Target {
+        match self {
+            Write::Register(reg, _) => Target::Register(*reg),
+            Write::Memory(addr, _) => Target::Memory(*addr),
+        }
+    }
+
+    pub fn value(&self) -> u16 {
+        match self {
+            Write::Register(_, value) | Write::Memory(_, value) => *value,
+        }
+    }
+}
+
+/// A register or a word of the data memory.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Target {
+    Register(Register),
+    Memory(u16),
+}
+
+/// An instruction in the history of a `Machine`, with the value it
+/// overwrote so that it can be undone.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Record {
+    /// The address of the instruction.
+    pub pc: u16,
+    pub write: Option<Write>,
+
+    /// The value `write` replaced, or 0 if there is no write.
+    pub old: u16,
+}
+
+/// Why `Machine::run` stopped.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Halt {
+    /// An instruction jumped to itself, as `:end BEZ r2, end` does at the
+    /// end of a program.
+    Loop,
+
+    /// The program counter reached an address to stop at.
+    Address,
+
+    /// The cycle limit was reached.
+    Limit,
+}
+
+impl Machine {
+    pub fn new(options: MachineOptions) -> Self {
+        Machine {
+            registers: [0; 8],
+            pc: 0,
+            imem: vec![0; options.imem_size],
+            dmem: vec![0; options.dmem_size],
+            cycles: 0,
+            history: VecDeque::new(),
+            capacity: options.history,
+        }
+    }
+
+    /// Creates a machine with the memory images of `program` loaded at
+    /// address 0.
+    pub fn with_program(program: &Program, options: MachineOptions) -> Result<Self> {
+        let mut machine = Machine::new(options);
+        let codes: Vec<u16> = program.codes.iter().map(|code| code.get_code()).collect();
+        let data: Vec<u16> = program.data.iter().map(|data| data.get_value()).collect();
+        machine.load_code(0, &codes)?;
+        machine.load_data(0, &data)?;
+
+        Ok(machine)
+    }
+
+    /// Writes `codes` into the instruction memory from `addr`.
+    pub fn load_code(&mut self, addr: u16, codes: &[u16]) -> Result<()> {
+        load(&mut self.imem, Section::Text, addr, codes)
+    }
+
+    /// Writes `words` into the data memory from `addr`.
+    pub fn load_data(&mut self, addr: u16, words: &[u16]) -> Result<()> {
+        load(&mut self.dmem, Section::Data, addr, words)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Returns the number of instructions run so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn register(&self, reg: Register) -> u16 {
+        self.registers[usize::from(reg.id())]
+    }
+
+    pub fn set_register(&mut self, reg: Register, value: u16) {
+        self.registers[usize::from(reg.id())] = value;
+    }
+
+    pub fn registers(&self) -> &[u16; 8] {
+        &self.registers
+    }
+
+    pub fn imem(&self) -> &[u16] {
+        &self.imem
+    }
+
+    pub fn dmem(&self) -> &[u16] {
+        &self.dmem
+    }
+
+    /// Returns the instruction word at `addr`.
+    pub fn fetch(&self, addr: u16) -> Result<u16> {
+        word(&self.imem, Section::Text, addr).copied()
+    }
+
+    /// Returns the data word at `addr`.
+    pub fn read(&self, addr: u16) -> Result<u16> {
+        word(&self.dmem, Section::Data, addr).copied()
+    }
+
+    pub fn write(&mut self, addr: u16, value: u16) -> Result<()> {
+        *word_mut(&mut self.dmem, Section::Data, addr)? = value;
+        Ok(())
+    }
+
+    /// Runs the instruction at the program counter.
+    ///
+    /// The machine is left unchanged if the instruction cannot be run.
+    pub fn step(&mut self) -> Result<Step> {
+        let pc = self.pc;
+        let code = self.fetch(pc)?;
+        let invalid = || Error::InvalidInstruction { code, address: pc };
+
+        let opcode = code >> 11;
+        let d = Register::from_id((code >> 8) & 0b111).unwrap();
+        let s = Register::from_id((code >> 5) & 0b111).unwrap();
+        let funct = code & 0b11111;
+        let immediate = code & 0b11111111;
+        // The displacements are sign-extended from 8 and 11 bits.
+        let disp8 = immediate as u8 as i8 as u16;
+        let disp11 = (((code & 0b111_11111111) << 5) as i16 >> 5) as u16;
+
+        let (rd, rs) = (self.register(d), self.register(s));
+        let mut next_pc = pc.wrapping_add(1);
+        let write = if opcode == 0 {
+            if let Some(funct) = FunctR::from_id(funct) {
+                match funct {
+                    FunctR::NOP => None,
+                    FunctR::MV => Some(Write::Register(d, rs)),
+                    FunctR::AND => Some(Write::Register(d, rd & rs)),
+                    FunctR::OR => Some(Write::Register(d, rd | rs)),
+                    FunctR::SL => Some(Write::Register(d, rd << 1)),
+                    FunctR::SR => Some(Write::Register(d, rd >> 1)),
+                    FunctR::ADD => Some(Write::Register(d, rd.wrapping_add(rs))),
+                    FunctR::SUB => Some(Write::Register(d, rd.wrapping_sub(rs))),
+                    // `ST` keeps the stored register in `dst` and the address in `src`.
+                    FunctR::ST => {
+                        self.read(rs)?;
+                        Some(Write::Memory(rs, rd))
+                    }
+                    FunctR::LD => Some(Write::Register(d, self.read(rs)?)),
+                }
+            } else {
+                match FunctJR::from_id(funct).ok_or_else(invalid)? {
+                    FunctJR::JR => {
+                        next_pc = rd;
+                        None
+                    }
+                    FunctJR::JALR => {
+                        next_pc = rd;
+                        Some(Write::Register(LINK_REGISTER, pc.wrapping_add(1)))
+                    }
+                }
+            }
+        } else if let Some(opcode) = OpcodeI::from_id(opcode) {
+            let value = match opcode {
+                OpcodeI::LDI => disp8,
+                OpcodeI::LDIU => immediate,
+                OpcodeI::ADDI => rd.wrapping_add(disp8),
+                OpcodeI::ADDIU => rd.wrapping_add(immediate),
+                OpcodeI::LDHI => immediate << 8,
+            };
+            Some(Write::Register(d, value))
+        } else if let Some(opcode) = OpcodeB::from_id(opcode) {
+            // The B format keeps the tested register where the others keep `dst`.
+            let taken = match opcode {
+                OpcodeB::BEZ => rd == 0,
+                OpcodeB::BNZ => rd != 0,
+                OpcodeB::BPL => (rd as i16) >= 0,
+                OpcodeB::BMI => (rd as i16) < 0,
+            };
+            if taken {
+                next_pc = pc.wrapping_add(disp8);
+            }
+            None
+        } else {
+            next_pc = pc.wrapping_add(disp11);
+            match OpcodeJ::from_id(opcode).ok_or_else(invalid)? {
+                OpcodeJ::JMP => None,
+                OpcodeJ::JAL => Some(Write::Register(LINK_REGISTER, pc.wrapping_add(1))),
+            }
+        };
+
+        let old = match write {
+            Some(write) => self.get(write.target())?,
+            None => 0,
+        };
+        if let Some(write) = write {
+            self.set(write.target(), write.value())?;
+        }
+        self.pc = next_pc;
+        self.cycles += 1;
+
+        if self.capacity > 0 {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(Record { pc, write, old });
+        }
+
+        Ok(Step {
+            pc,
+            code,
+            next_pc,
+            write,
+        })
+    }
+
+    /// Reverts the last instruction run, returning it, or `None` when the
+    /// history is empty.
+    pub fn undo(&mut self) -> Option<Record> {
+        let record = self.history.pop_back()?;
+        if let Some(write) = record.write {
+            // The target was written once already, so it is in range.
+            self.set(write.target(), record.old).unwrap();
+        }
+        self.pc = record.pc;
+        self.cycles -= 1;
+
+        Some(record)
+    }
+
+    /// Returns the remembered instructions, oldest first, with the cycle
+    /// each ran in, counting from 0.
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = (u64, &Record)> {
+        let first = self.cycles - self.history.len() as u64;
+        self.history
+            .iter()
+            .enumerate()
+            .map(move |(index, record)| (first + index as u64, record))
+    }
+
+    /// Returns the last remembered instruction that wrote `target`, with
+    /// the cycle it ran in.
+    pub fn last_write(&self, target: Target) -> Option<(u64, &Record)> {
+        self.history()
+            .rev()
+            .find(|(_, record)| matches!(record.write, Some(write) if write.target() == target))
+    }
+
+    /// Returns the value of a register or a data word.
+    pub fn get(&self, target: Target) -> Result<u16> {
+        match target {
+            Target::Register(reg) => Ok(self.register(reg)),
+            Target::Memory(addr) => self.read(addr),
+        }
+    }
+
+    fn set(&mut self, target: Target, value: u16) -> Result<()> {
+        match target {
+            Target::Register(reg) => self.set_register(reg, value),
+            Target::Memory(addr) => self.write(addr, value)?,
+        }
+
+        Ok(())
+    }
+
+    /// Runs instructions until one jumps to itself, or until `max_cycles`
+    /// instructions have been run.
+    pub fn run(&mut self, max_cycles: u64) -> Result<Halt> {
+        self.run_until(max_cycles, |_| false)
+    }
+
+    /// Like `run`, but also stops before running an instruction whose
+    /// address `stop` accepts, including the first one.
+    pub fn run_until<F>(&mut self, max_cycles: u64, stop: F) -> Result<Halt>
+    where
+        F: FnMut(u16) -> bool,
+    {
+        self.run_traced(max_cycles, stop, |_, _| {})
+    }
+
+    /// Like `run_until`, but also passes each instruction run to `trace`,
+    /// with the cycle it ran in.
+    pub fn run_traced<F, G>(&mut self, max_cycles: u64, mut stop: F, mut trace: G) -> Result<Halt>
+    where
+        F: FnMut(u16) -> bool,
+        G: FnMut(u64, &Step),
+    {
+        for _ in 0..max_cycles {
+            if stop(self.pc) {
+                return Ok(Halt::Address);
+            }
+
+            let step = self.step()?;
+            trace(self.cycles - 1, &step);
+            if step.next_pc == step.pc {
+                return Ok(Halt::Loop);
+            }
+        }
+
+        Ok(Halt::Limit)
+    }
+}
+
+fn load(memory: &mut [u16], section: Section, addr: u16, words: &[u16]) -> Result<()> {
+    let start = usize::from(addr);
+    let end = start + words.len();
+    if end > memory.len() {
+        return Err(Error::AddressOutOfRange {
+            section,
+            address: (end - 1) as u16,
+            size: memory.len(),
+        });
+    }
+
+    memory[start..end].copy_from_slice(words);
+    Ok(())
+}
+
+fn word(memory: &[u16], section: Section, addr: u16) -> Result<&u16> {
+    memory
+        .get(usize::from(addr))
+        .ok_or(Error::AddressOutOfRange {
+            section,
+            address: addr,
+            size: memory.len(),
+        })
+}
+
+fn word_mut(memory: &mut [u16], section: Section, addr: u16) -> Result<&mut u16> {
+    let size = memory.len();
+    memory
+        .get_mut(usize::from(addr))
+        .ok_or(Error::AddressOutOfRange {
+            section,
+            address: addr,
+            size,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble_items, parser::parse_file};
+
+    fn boot(source: &str) -> Machine {
+        let program = assemble_items(parse_file(source).unwrap()).unwrap();
+        Machine::with_program(&program, MachineOptions::default()).unwrap()
+    }
+
+    fn registers(machine: &Machine) -> Vec<i16> {
+        machine.registers().iter().map(|&reg| reg as i16).collect()
+    }
+
+    #[test]
+    fn mult() {
+        let mut machine = boot(
+            "LDIU r0, #2
+            LD r1, (r0)
+            LDIU r0, #3
+            LD r2, (r0)
+            :loop
+            ADD r3, r1
+            ADDI r2, #-1
+            BNZ r2, loop
+            LDIU r0, #0
+            ST r3, (r0)
+            :end
+            BEZ r2, end",
+        );
+        machine.load_data(2, &[5, 7]).unwrap();
+
+        assert_eq!(machine.run(1000).unwrap(), Halt::Loop);
+        assert_eq!(machine.read(0).unwrap(), 35);
+        assert_eq!(machine.pc(), 9);
+        assert_eq!(machine.cycles(), 4 + 3 * 7 + 3);
+
+        let mut machine = boot(":loop\nADDI r0, #1\nJMP loop");
+        assert_eq!(machine.run_until(10, |pc| pc == 0).unwrap(), Halt::Address);
+        assert_eq!(machine.cycles(), 0);
+        machine.step().unwrap();
+        assert_eq!(machine.run_until(10, |pc| pc == 0).unwrap(), Halt::Address);
+        assert_eq!(machine.register(Register::R0), 1);
+        assert_eq!(machine.run(11).unwrap(), Halt::Limit);
+        assert_eq!(machine.register(Register::R0), 7);
+    }
+
+    #[test]
+    fn step_r() {
+        let mut machine = boot(
+            "LDI r0, #12
+            LDI r1, #10
+            MV r2, r0
+            AND r2, r1
+            MV r3, r0
+            OR r3, r1
+            MV r4, r0
+            ADD r4, r1
+            MV r5, r0
+            SUB r5, r1
+            SL r0
+            SR r1
+            LDI r6, #-2
+            SR r6
+            NOP",
+        );
+        assert_eq!(machine.run(15).unwrap(), Halt::Limit);
+        assert_eq!(registers(&machine), vec![24, 5, 8, 14, 22, 2, 0x7fff, 0]);
+
+        let mut machine = boot(
+            "LDI r0, #3
+            LDI r1, #-7
+            ST r1, (r0)
+            LD r2, (r0)",
+        );
+        machine.run(2).unwrap();
+        assert_eq!(
+            machine.step().unwrap(),
+            Step {
+                pc: 2,
+                code: 0b00000_001_000_01000,
+                next_pc: 3,
+                write: Some(Write::Memory(3, -7i16 as u16)),
+            }
+        );
+        assert_eq!(
+            machine.step().unwrap().write,
+            Some(Write::Register(Register::R2, -7i16 as u16))
+        );
+    }
+
+    #[test]
+    fn step_i() {
+        let mut machine = boot(
+            "LDI r0, #-1
+            LDIU r1, #255
+            LDHI r2, #0x12
+            ADDIU r2, #0x34
+            LDI r3, #100
+            ADDI r3, #-101
+            ADDIU r0, #1",
+        );
+        machine.run(7).unwrap();
+        assert_eq!(registers(&machine), vec![0, 255, 0x1234, -1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn step_b() {
+        let program = "LDI r0, #{}
+            BEZ r0, zero
+            BMI r0, minus
+            BPL r0, plus
+            :zero
+            LDI r1, #1
+            :minus
+            LDI r1, #2
+            :plus
+            LDI r1, #3";
+        // Runs the load and the branches up to the one taken.
+        let destination = |value: i16, steps: u64| {
+            let mut machine = boot(&program.replace("{}", &value.to_string()));
+            machine.run(steps).unwrap();
+            machine.pc()
+        };
+        assert_eq!(destination(0, 2), 4);
+        assert_eq!(destination(-5, 3), 5);
+        assert_eq!(destination(5, 4), 6);
+
+        let mut machine = boot("LDI r0, #1\n:back\nBNZ r0, back");
+        machine.run(2).unwrap();
+        assert_eq!(machine.pc(), 1);
+
+        let mut machine = boot("LDI r0, #0\n:back\nBNZ r0, back");
+        machine.run(2).unwrap();
+        assert_eq!(machine.pc(), 2);
+    }
+
+    #[test]
+    fn step_j() {
+        let mut machine = boot(
+            "JAL f
+            :end
+            JMP end
+            NOP
+            :f
+            LDI r0, #g
+            JALR r0
+            NOP
+            :g
+            JR r7",
+        );
+        assert_eq!(machine.step().unwrap().next_pc, 3);
+        assert_eq!(machine.register(Register::R7), 1);
+        machine.run(2).unwrap();
+        assert_eq!(machine.pc(), 6);
+        assert_eq!(machine.register(Register::R7), 5);
+        machine.run(1).unwrap();
+        assert_eq!(machine.pc(), 5);
+
+        // `JALR r7` jumps to the old return address.
+        let mut machine = boot("LDI r7, #3\nJALR r7");
+        machine.run(2).unwrap();
+        assert_eq!(machine.pc(), 3);
+        assert_eq!(machine.register(Register::R7), 2);
+
+        // The program counter wraps around.
+        let mut machine = boot(":back\nJMP back");
+        machine.set_pc(0xffff);
+        machine.load_code(0xffff, &[0b10100_00000000001]).unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.pc(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        let mut machine = boot("NOP");
+        machine.load_code(1, &[0b11111_000_00000000]).unwrap();
+        machine.step().unwrap();
+        match machine.step() {
+            Err(Error::InvalidInstruction { code, address }) => {
+                assert_eq!(code, 0b11111_000_00000000);
+                assert_eq!(address, 1);
+            }
+            result => panic!("expected an invalid instruction error, got {:?}", result),
+        }
+        assert_eq!(machine.pc(), 1);
+        assert_eq!(machine.cycles(), 1);
+
+        // An unknown R format function.
+        machine.load_code(1, &[0b00000_000_000_11111]).unwrap();
+        assert!(matches!(
+            machine.step(),
+            Err(Error::InvalidInstruction { .. })
+        ));
+
+        let options = MachineOptions {
+            imem_size: 4,
+            dmem_size: 2,
+            ..MachineOptions::default()
+        };
+        let program =
+            assemble_items(parse_file("LDI r0, #2\nLDI r1, #9\nST r1, (r0)").unwrap()).unwrap();
+        let mut machine = Machine::with_program(&program, options).unwrap();
+        machine.run(2).unwrap();
+        match machine.step() {
+            Err(Error::AddressOutOfRange {
+                section,
+                address,
+                size,
+            }) => {
+                assert_eq!(section, Section::Data);
+                assert_eq!(address, 2);
+                assert_eq!(size, 2);
+            }
+            result => panic!("expected an address out of range error, got {:?}", result),
+        }
+        assert_eq!(machine.pc(), 2);
+
+        // Running off the end of the instruction memory.
+        machine.set_pc(3);
+        machine.step().unwrap();
+        assert!(matches!(
+            machine.step(),
+            Err(Error::AddressOutOfRange {
+                section: Section::Text,
+                address: 4,
+                ..
+            })
+        ));
+        assert!(machine.load_code(2, &[0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn undo() {
+        let program = assemble_items(
+            parse_file("LDI r0, #5\nLDI r1, #1\nST r0, (r1)\nADDI r0, #1\nST r0, (r1)").unwrap(),
+        )
+        .unwrap();
+        let options = MachineOptions {
+            history: 3,
+            ..MachineOptions::default()
+        };
+        let mut machine = Machine::with_program(&program, options).unwrap();
+        machine.run(2).unwrap();
+        let before = machine.clone();
+
+        machine.run(3).unwrap();
+        assert_eq!(machine.read(1).unwrap(), 6);
+        assert_eq!(
+            machine.last_write(Target::Memory(1)),
+            Some((
+                4,
+                &Record {
+                    pc: 4,
+                    write: Some(Write::Memory(1, 6)),
+                    old: 5
+                }
+            ))
+        );
+        assert_eq!(
+            machine
+                .last_write(Target::Register(Register::R0))
+                .unwrap()
+                .0,
+            3
+        );
+        // `r1` was written before the remembered instructions.
+        assert_eq!(machine.last_write(Target::Register(Register::R1)), None);
+
+        let pcs: Vec<u16> = std::iter::from_fn(|| machine.undo())
+            .map(|record| record.pc)
+            .collect();
+        assert_eq!(pcs, vec![4, 3, 2]);
+        assert_eq!(machine.registers(), before.registers());
+        assert_eq!(machine.dmem(), before.dmem());
+        assert_eq!(machine.pc(), 2);
+        assert_eq!(machine.cycles(), 2);
+
+        // Nothing is remembered by default.
+        let mut machine = Machine::with_program(&program, MachineOptions::default()).unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.undo(), None);
+        assert_eq!(machine.history().count(), 0);
+    }
+}
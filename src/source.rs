@@ -0,0 +1,168 @@
+use crate::{
+    error::Error,
+    span::{FileId, Location, Span},
+};
+use std::rc::Rc;
+
+/// A source text read by the assembler: a file, or the body of a macro
+/// pasted at a call site.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceFile {
+    /// The path of the file, as given on the command line or resolved from
+    /// an `.include`. An expansion has the name of the file that defines
+    /// the macro.
+    pub name: String,
+    pub source: Rc<str>,
+
+    /// Where the text comes from, or `None` for the file given on the
+    /// command line.
+    pub origin: Option<Origin>,
+}
+
+/// How a source text became part of the program.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Origin {
+    /// Read by the `.include` directive at the span.
+    Include(Span),
+
+    /// The body of the macro `name`, expanded at `call_site`.
+    Macro { name: String, call_site: Span },
+}
+
+impl Origin {
+    /// Returns the span of the `.include` or of the macro call.
+    pub fn span(&self) -> Span {
+        match self {
+            Origin::Include(span) => *span,
+            Origin::Macro { call_site, .. } => *call_site,
+        }
+    }
+}
+
+/// The source texts of a program, indexed by `FileId`.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn add<N, S>(&mut self, name: N, source: S, origin: Option<Origin>) -> FileId
+    where
+        N: Into<String>,
+        S: Into<Rc<str>>,
+    {
+        self.files.push(SourceFile {
+            name: name.into(),
+            source: source.into(),
+            origin,
+        });
+
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    /// Adds an expansion of the macro `name`, whose body is written in
+    /// `body`, sharing the text of that file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `body` is not in the map.
+    pub fn expand(&mut self, body: FileId, name: &str, call_site: Span) -> FileId {
+        let file = &self.files[body.0 as usize];
+        let (file_name, source) = (file.name.clone(), file.source.clone());
+
+        self.add(
+            file_name,
+            source,
+            Some(Origin::Macro {
+                name: name.to_string(),
+                call_site,
+            }),
+        )
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0 as usize)
+    }
+
+    /// Returns the `.include` directives and macro calls that led to
+    /// `file`, innermost first.
+    pub fn backtrace(&self, file: FileId) -> Vec<&Origin> {
+        let mut trace = Vec::new();
+        let mut current = self.get(file);
+        while let Some(origin) = current.and_then(|file| file.origin.as_ref()) {
+            // Guard against a malformed map whose origins form a cycle.
+            if trace.len() >= self.files.len() {
+                break;
+            }
+
+            trace.push(origin);
+            current = self.get(origin.span().start.file);
+        }
+
+        trace
+    }
+
+    /// Returns the locations of the `.include` directives and macro calls
+    /// that led to `location`, outermost first, followed by `location`.
+    ///
+    /// Sorting by the trail puts locations in the order they are assembled.
+    pub fn trail(&self, location: Location) -> Vec<Location> {
+        let mut trail: Vec<Location> = self
+            .backtrace(location.file)
+            .into_iter()
+            .map(|origin| origin.span().start)
+            .collect();
+        trail.reverse();
+        trail.push(location);
+
+        trail
+    }
+
+    /// Sorts `errors` in the order their spans are assembled.
+    pub fn sort_errors(&self, errors: &mut [Error]) {
+        errors.sort_by_cached_key(|err| err.span().map(|span| self.trail(span.start)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backtrace() {
+        let at = |file: FileId, line: u32| {
+            let mut location = Location::new(0, line, 1);
+            location.file = file;
+            Span::new(location, location)
+        };
+
+        let mut sources = SourceMap::new();
+        let main = sources.add("main.asm", ".include \"lib.asm\"\nsquare r0", None);
+        let lib = sources.add(
+            "lib.asm",
+            ".macro square x\n\tADD x, x\n.endm",
+            Some(Origin::Include(at(main, 1))),
+        );
+        let square = sources.expand(lib, "square", at(main, 2));
+
+        assert_eq!(main, FileId(0));
+        assert_eq!(sources.get(square).unwrap().name, "lib.asm");
+        assert_eq!(
+            sources.backtrace(square),
+            vec![&Origin::Macro {
+                name: "square".to_string(),
+                call_site: at(main, 2)
+            }]
+        );
+        assert_eq!(sources.backtrace(lib), vec![&Origin::Include(at(main, 1))]);
+        assert!(sources.backtrace(main).is_empty());
+        assert_eq!(sources.get(FileId(3)), None);
+
+        let in_body = at(square, 2).start;
+        assert_eq!(sources.trail(in_body), vec![at(main, 2).start, in_body]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Identifies a source file in a `SourceMap`. The default, 0, is the file
+/// given on the command line.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct FileId(pub u32);
+
+/// A position in the source text.
+///
+/// `line` and `column` are 1-based; a default (all zero) location means the
+/// position is unknown, e.g. for items constructed by hand. The fields are
+/// 32 bits wide to keep spans, and the errors that carry them, small.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Location {
+    pub file: FileId,
+
+    /// Byte offset from the beginning of the source.
+    pub offset: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Location {
+    /// A location in the first file.
+    pub fn new(offset: u32, line: u32, column: u32) -> Self {
+        Location {
+            file: FileId::default(),
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A range of the source text, `start` inclusive and `end` exclusive.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn new(start: Location, end: Location) -> Self {
+        Span { start, end }
+    }
+
+    /// Returns the smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
+/// A value together with the span of the source it was parsed from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Spanned::new(node, Span::default())
+    }
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.node)
+    }
+}
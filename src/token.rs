@@ -1,11 +1,16 @@
+use std::fmt::{self, Display, Formatter};
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    /// A number token: `1`
-    Num(String),
+    /// A number token: `1`, `0x1f`, `'A'`
+    Num(i64),
 
     /// A ident token: `label0`, `end`
     Ident(String),
 
+    /// A string literal, with its escapes resolved: `"abc\n"`
+    Str(String),
+
     /// The `NOP` token.
     NOP,
 
@@ -75,6 +80,99 @@ pub enum Token {
     /// The `JALR` token.
     JALR,
 
+    /// The `LI` pseudo-instruction token.
+    LI,
+
+    /// The `LA` pseudo-instruction token.
+    LA,
+
+    /// The `CLR` pseudo-instruction token.
+    CLR,
+
+    /// The `NOT` pseudo-instruction token.
+    NOT,
+
+    /// The `NEG` pseudo-instruction token.
+    NEG,
+
+    /// The `INC` pseudo-instruction token.
+    INC,
+
+    /// The `DEC` pseudo-instruction token.
+    DEC,
+
+    /// The `B` pseudo-instruction token.
+    B,
+
+    /// The `CALL` pseudo-instruction token.
+    CALL,
+
+    /// The `RET` pseudo-instruction token.
+    RET,
+
+    /// The `PUSH` pseudo-instruction token.
+    PUSH,
+
+    /// The `POP` pseudo-instruction token.
+    POP,
+
+    /// The `.equ` token.
+    Equ,
+
+    /// The `.set` token.
+    Set,
+
+    /// The `.text` token.
+    Text,
+
+    /// The `.data` token.
+    Data,
+
+    /// The `.word` token.
+    Word,
+
+    /// The `.byte` token.
+    Byte,
+
+    /// The `.space` token.
+    Space,
+
+    /// The `.ascii` token.
+    Ascii,
+
+    /// The `.string` token.
+    String,
+
+    /// The `.org` token.
+    Org,
+
+    /// The `.align` token.
+    Align,
+
+    /// The `.include` token.
+    Include,
+
+    /// The `.macro` token.
+    Macro,
+
+    /// The `.endm` token.
+    Endm,
+
+    /// The `.if` token.
+    If,
+
+    /// The `.ifdef` token.
+    Ifdef,
+
+    /// The `.ifndef` token.
+    Ifndef,
+
+    /// The `.else` token.
+    Else,
+
+    /// The `.endif` token.
+    Endif,
+
     /// The `R0` token.
     R0,
 
@@ -105,6 +203,36 @@ pub enum Token {
     /// The `-` token.
     Minus,
 
+    /// The `+` token.
+    Plus,
+
+    /// The `*` token.
+    Star,
+
+    /// The `/` token.
+    Slash,
+
+    /// The `%` token.
+    Percent,
+
+    /// The `<<` token.
+    Shl,
+
+    /// The `>>` token.
+    Shr,
+
+    /// The `&` token.
+    Ampersand,
+
+    /// The `|` token.
+    Pipe,
+
+    /// The `^` token.
+    Caret,
+
+    /// The `~` token.
+    Tilde,
+
     /// The `,` token.
     Comma,
 
@@ -116,4 +244,222 @@ pub enum Token {
 
     /// The `)` token
     CloseParen,
+
+    /// The end of a line. The lexer also emits one at the end of the input when
+    /// the last line is not terminated.
+    Newline,
+}
+
+impl Token {
+    /// Returns the keyword spelled exactly `word`: mnemonics are uppercase and
+    /// registers lowercase.
+    pub fn keyword(word: &str) -> Option<Token> {
+        let token = match word {
+            "NOP" => Token::NOP,
+            "MV" => Token::MV,
+            "AND" => Token::AND,
+            "OR" => Token::OR,
+            "SL" => Token::SL,
+            "SR" => Token::SR,
+            "ADD" => Token::ADD,
+            "SUB" => Token::SUB,
+            "ST" => Token::ST,
+            "LD" => Token::LD,
+
+            "LDI" => Token::LDI,
+            "LDIU" => Token::LDIU,
+            "ADDI" => Token::ADDI,
+            "ADDIU" => Token::ADDIU,
+            "LDHI" => Token::LDHI,
+
+            "BEZ" => Token::BEZ,
+            "BNZ" => Token::BNZ,
+            "BPL" => Token::BPL,
+            "BMI" => Token::BMI,
+
+            "JMP" => Token::JMP,
+            "JAL" => Token::JAL,
+            "JR" => Token::JR,
+            "JALR" => Token::JALR,
+
+            "LI" => Token::LI,
+            "LA" => Token::LA,
+            "CLR" => Token::CLR,
+            "NOT" => Token::NOT,
+            "NEG" => Token::NEG,
+            "INC" => Token::INC,
+            "DEC" => Token::DEC,
+            "B" => Token::B,
+            "CALL" => Token::CALL,
+            "RET" => Token::RET,
+            "PUSH" => Token::PUSH,
+            "POP" => Token::POP,
+
+            ".equ" => Token::Equ,
+            ".set" => Token::Set,
+            ".text" => Token::Text,
+            ".data" => Token::Data,
+            ".word" => Token::Word,
+            ".byte" => Token::Byte,
+            ".space" => Token::Space,
+            ".ascii" => Token::Ascii,
+            ".string" => Token::String,
+            ".org" => Token::Org,
+            ".align" => Token::Align,
+            ".include" => Token::Include,
+            ".macro" => Token::Macro,
+            ".endm" => Token::Endm,
+            ".if" => Token::If,
+            ".ifdef" => Token::Ifdef,
+            ".ifndef" => Token::Ifndef,
+            ".else" => Token::Else,
+            ".endif" => Token::Endif,
+
+            "r0" => Token::R0,
+            "r1" => Token::R1,
+            "r2" => Token::R2,
+            "r3" => Token::R3,
+            "r4" => Token::R4,
+            "r5" => Token::R5,
+            "r6" => Token::R6,
+            "r7" => Token::R7,
+
+            _ => return None,
+        };
+
+        Some(token)
+    }
+
+    /// Returns whether the token names a register.
+    pub fn is_register(&self) -> bool {
+        matches!(
+            self,
+            Token::R0
+                | Token::R1
+                | Token::R2
+                | Token::R3
+                | Token::R4
+                | Token::R5
+                | Token::R6
+                | Token::R7
+        )
+    }
+
+    /// Returns whether the token names a pseudo-instruction. These are only
+    /// keywords in the place of a mnemonic.
+    pub fn is_pseudo(&self) -> bool {
+        matches!(
+            self,
+            Token::LI
+                | Token::LA
+                | Token::CLR
+                | Token::NOT
+                | Token::NEG
+                | Token::INC
+                | Token::DEC
+                | Token::B
+                | Token::CALL
+                | Token::RET
+                | Token::PUSH
+                | Token::POP
+        )
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Num(num) => write!(f, "{}", num),
+            Token::Ident(ident) => write!(f, "{}", ident),
+            Token::Str(text) => write!(f, "{:?}", text),
+
+            Token::NOP => write!(f, "NOP"),
+            Token::MV => write!(f, "MV"),
+            Token::AND => write!(f, "AND"),
+            Token::OR => write!(f, "OR"),
+            Token::SL => write!(f, "SL"),
+            Token::SR => write!(f, "SR"),
+            Token::ADD => write!(f, "ADD"),
+            Token::SUB => write!(f, "SUB"),
+            Token::ST => write!(f, "ST"),
+            Token::LD => write!(f, "LD"),
+
+            Token::LDI => write!(f, "LDI"),
+            Token::LDIU => write!(f, "LDIU"),
+            Token::ADDI => write!(f, "ADDI"),
+            Token::ADDIU => write!(f, "ADDIU"),
+            Token::LDHI => write!(f, "LDHI"),
+
+            Token::BEZ => write!(f, "BEZ"),
+            Token::BNZ => write!(f, "BNZ"),
+            Token::BPL => write!(f, "BPL"),
+            Token::BMI => write!(f, "BMI"),
+
+            Token::JMP => write!(f, "JMP"),
+            Token::JAL => write!(f, "JAL"),
+            Token::JR => write!(f, "JR"),
+            Token::JALR => write!(f, "JALR"),
+
+            Token::LI => write!(f, "LI"),
+            Token::LA => write!(f, "LA"),
+            Token::CLR => write!(f, "CLR"),
+            Token::NOT => write!(f, "NOT"),
+            Token::NEG => write!(f, "NEG"),
+            Token::INC => write!(f, "INC"),
+            Token::DEC => write!(f, "DEC"),
+            Token::B => write!(f, "B"),
+            Token::CALL => write!(f, "CALL"),
+            Token::RET => write!(f, "RET"),
+            Token::PUSH => write!(f, "PUSH"),
+            Token::POP => write!(f, "POP"),
+
+            Token::Equ => write!(f, ".equ"),
+            Token::Set => write!(f, ".set"),
+            Token::Text => write!(f, ".text"),
+            Token::Data => write!(f, ".data"),
+            Token::Word => write!(f, ".word"),
+            Token::Byte => write!(f, ".byte"),
+            Token::Space => write!(f, ".space"),
+            Token::Ascii => write!(f, ".ascii"),
+            Token::String => write!(f, ".string"),
+            Token::Org => write!(f, ".org"),
+            Token::Align => write!(f, ".align"),
+            Token::Include => write!(f, ".include"),
+            Token::Macro => write!(f, ".macro"),
+            Token::Endm => write!(f, ".endm"),
+            Token::If => write!(f, ".if"),
+            Token::Ifdef => write!(f, ".ifdef"),
+            Token::Ifndef => write!(f, ".ifndef"),
+            Token::Else => write!(f, ".else"),
+            Token::Endif => write!(f, ".endif"),
+
+            Token::R0 => write!(f, "r0"),
+            Token::R1 => write!(f, "r1"),
+            Token::R2 => write!(f, "r2"),
+            Token::R3 => write!(f, "r3"),
+            Token::R4 => write!(f, "r4"),
+            Token::R5 => write!(f, "r5"),
+            Token::R6 => write!(f, "r6"),
+            Token::R7 => write!(f, "r7"),
+
+            Token::Sharp => write!(f, "#"),
+            Token::Minus => write!(f, "-"),
+            Token::Plus => write!(f, "+"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Shl => write!(f, "<<"),
+            Token::Shr => write!(f, ">>"),
+            Token::Ampersand => write!(f, "&"),
+            Token::Pipe => write!(f, "|"),
+            Token::Caret => write!(f, "^"),
+            Token::Tilde => write!(f, "~"),
+            Token::Comma => write!(f, ","),
+            Token::Colon => write!(f, ":"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+
+            Token::Newline => write!(f, "\\n"),
+        }
+    }
 }
@@ -0,0 +1,590 @@
+use crate::{
+    code::Program,
+    error::{Error, Result},
+    register::Register,
+    sim::{Step, Write},
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    iter::Peekable,
+    str::Chars,
+};
+
+/// How a trace is written. Every format has the same fields, written the
+/// same way:
+///
+/// - `cycle`: the cycle the instruction ran in, from 0, in decimal
+/// - `pc`: the address of the instruction, in 4 hexadecimal digits
+/// - `code`: the instruction word, in 16 binary digits
+/// - `write`: the register or data word written, `r3=0005` or
+///   `[0010]=0023` in hexadecimal, or `-` when nothing is written
+/// - `disasm`: the instruction as assembled, for people to read
+///
+/// A testbench can print the first four with
+/// `$display("%0d %h %b r%0d=%h", cycle, pc, code, dst, value)`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// `4 0004 0000001100100110 r3=0005 ; ADD r3, r1`
+    Text,
+
+    /// `4,0004,0000001100100110,r3=0005,"ADD r3, r1"`, after a header line
+    Csv,
+
+    /// `{"cycle":4,"pc":"0004","code":"0000001100100110","write":"r3=0005","disasm":"ADD r3, r1"}`,
+    /// one object per line. `write` is `null` when nothing is written.
+    Json,
+}
+
+impl Format {
+    /// Returns the format called `name`: `text`, `csv` or `json`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// Returns the line that starts a trace, if the format has one.
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            Format::Csv => Some("cycle,pc,code,write,disasm"),
+            Format::Text | Format::Json => None,
+        }
+    }
+
+    /// Writes `entry` as a line, without the line break.
+    pub fn format(&self, entry: &Entry) -> String {
+        let write = write_field(entry.write);
+        match self {
+            Format::Text => entry.to_string(),
+            Format::Csv => format!(
+                "{},{:04x},{:016b},{},\"{}\"",
+                entry.cycle,
+                entry.pc,
+                entry.code,
+                write,
+                entry.disasm.replace('"', "\"\"")
+            ),
+            Format::Json => format!(
+                "{{\"cycle\":{},\"pc\":\"{:04x}\",\"code\":\"{:016b}\",\"write\":{},\"disasm\":{}}}",
+                entry.cycle,
+                entry.pc,
+                entry.code,
+                match entry.write {
+                    Some(_) => json_string(&write),
+                    None => "null".to_string(),
+                },
+                json_string(&entry.disasm)
+            ),
+        }
+    }
+}
+
+/// An instruction in a trace.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub code: u16,
+    pub write: Option<Write>,
+
+    /// The instruction as assembled, or empty if it is not known.
+    pub disasm: String,
+}
+
+impl Entry {
+    /// Describes `step`, run in `cycle`, disassembling it with `program`.
+    pub fn new(cycle: u64, step: &Step, program: &Program) -> Self {
+        let disasm = match program.codes.get(usize::from(step.pc)) {
+            Some(code) => match code.get_mnemonic() {
+                Some(mnemonic) => mnemonic.to_string(),
+                None => "pad".to_string(),
+            },
+            None => String::new(),
+        };
+
+        Entry {
+            cycle,
+            pc: step.pc,
+            code: step.code,
+            write: step.write,
+            disasm,
+        }
+    }
+
+    /// Returns whether both entries ran the same instruction with the same
+    /// effect. The cycles and the disassembly are not compared, since a
+    /// testbench may count cycles from reset and print no disassembly.
+    pub fn matches(&self, other: &Entry) -> bool {
+        self.pc == other.pc && self.code == other.code && self.write == other.write
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04x} {:016b} {}",
+            self.cycle,
+            self.pc,
+            self.code,
+            write_field(self.write)
+        )?;
+        if !self.disasm.is_empty() {
+            write!(f, " ; {}", self.disasm)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_field(write: Option<Write>) -> String {
+    match write {
+        Some(Write::Register(reg, value)) => format!("{}={:04x}", reg, value),
+        Some(Write::Memory(addr, value)) => format!("[{:04x}]={:04x}", addr, value),
+        None => "-".to_string(),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads a trace in any of the formats; the format is told line by line.
+/// Empty lines, lines starting with `#` or `//` and CSV headers are skipped.
+pub fn parse(file: &str, text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("//")
+            || line.starts_with("cycle,")
+        {
+            continue;
+        }
+
+        let entry = if line.starts_with('{') {
+            parse_json(line)
+        } else if line.split(';').next().unwrap().contains(',') {
+            parse_csv(line)
+        } else {
+            parse_text(line)
+        };
+        entries.push(entry.map_err(|reason| Error::InvalidTrace {
+            file: file.to_string(),
+            line: index + 1,
+            reason,
+        })?);
+    }
+
+    Ok(entries)
+}
+
+fn parse_text(line: &str) -> std::result::Result<Entry, String> {
+    let (fields, disasm) = line.split_once(';').unwrap_or((line, ""));
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    match fields.as_slice() {
+        [cycle, pc, code, write] => parse_fields(cycle, pc, code, write, disasm.trim()),
+        _ => Err(format!("expected 4 fields, found {}", fields.len())),
+    }
+}
+
+fn parse_csv(line: &str) -> std::result::Result<Entry, String> {
+    let fields: Vec<&str> = line.splitn(5, ',').collect();
+    match fields.as_slice() {
+        [cycle, pc, code, write] => parse_fields(cycle, pc, code, write, ""),
+        [cycle, pc, code, write, disasm] => {
+            let disasm = disasm.trim();
+            let disasm = match disasm.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\"\"", "\""),
+                None => disasm.to_string(),
+            };
+            parse_fields(cycle, pc, code, write, &disasm)
+        }
+        _ => Err(format!("expected 4 or 5 fields, found {}", fields.len())),
+    }
+}
+
+fn parse_json(line: &str) -> std::result::Result<Entry, String> {
+    let fields = JsonObject::parse(line)?;
+    let string = |key: &str| match fields.get(key) {
+        Some(Json::String(s)) => Ok(s.as_str()),
+        _ => Err(format!("expected a string `{}`", key)),
+    };
+
+    let cycle = match fields.get("cycle") {
+        Some(Json::Number(cycle)) => cycle.to_string(),
+        _ => return Err("expected a number `cycle`".to_string()),
+    };
+    let write = match fields.get("write") {
+        Some(Json::Null) => "-",
+        _ => string("write")?,
+    };
+    let disasm = match fields.get("disasm") {
+        Some(_) => string("disasm")?,
+        None => "",
+    };
+
+    parse_fields(&cycle, string("pc")?, string("code")?, write, disasm)
+}
+
+fn parse_fields(
+    cycle: &str,
+    pc: &str,
+    code: &str,
+    write: &str,
+    disasm: &str,
+) -> std::result::Result<Entry, String> {
+    let cycle = cycle
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{}` is not a cycle", cycle))?;
+    let pc = hex(pc)?;
+    let code = u16::from_str_radix(&code.trim().replace('_', ""), 2)
+        .ok()
+        .filter(|_| code.trim().replace('_', "").len() == 16)
+        .ok_or_else(|| format!("`{}` is not a 16-bit binary word", code))?;
+
+    let write = write.trim();
+    let write = if write == "-" {
+        None
+    } else {
+        let (target, value) = write
+            .split_once('=')
+            .ok_or_else(|| format!("`{}` is not a write", write))?;
+        let value = hex(value)?;
+        match target.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(addr) => Some(Write::Memory(hex(addr)?, value)),
+            None => {
+                let reg = target
+                    .strip_prefix('r')
+                    .and_then(|id| id.parse().ok())
+                    .and_then(Register::from_id)
+                    .ok_or_else(|| format!("`{}` is not a register", target))?;
+                Some(Write::Register(reg, value))
+            }
+        }
+    };
+
+    Ok(Entry {
+        cycle,
+        pc,
+        code,
+        write,
+        disasm: disasm.to_string(),
+    })
+}
+
+/// Parses a word in at most 4 hexadecimal digits.
+fn hex(s: &str) -> std::result::Result<u16, String> {
+    let s = s.trim();
+    match u16::from_str_radix(s, 16) {
+        Ok(value) if s.len() <= 4 => Ok(value),
+        _ => Err(format!("`{}` is not a hexadecimal word", s)),
+    }
+}
+
+/// A value of a flat JSON object.
+#[derive(Debug, PartialEq)]
+enum Json {
+    String(String),
+    Number(u64),
+    Null,
+}
+
+/// A JSON object whose values are strings, numbers or `null`, as written
+/// by `Format::Json`.
+struct JsonObject(Vec<(String, Json)>);
+
+impl JsonObject {
+    fn get(&self, key: &str) -> Option<&Json> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, String> {
+        let mut chars = s.chars().peekable();
+        let mut fields = Vec::new();
+
+        expect(&mut chars, '{')?;
+        if skip_whitespace(&mut chars) == Some('}') {
+            chars.next();
+        } else {
+            loop {
+                skip_whitespace(&mut chars);
+                let key = json_string_literal(&mut chars)?;
+                expect(&mut chars, ':')?;
+                let value = match skip_whitespace(&mut chars) {
+                    Some('"') => Json::String(json_string_literal(&mut chars)?),
+                    Some('n') => {
+                        for c in "null".chars() {
+                            expect(&mut chars, c)?;
+                        }
+                        Json::Null
+                    }
+                    Some(c) if c.is_ascii_digit() => {
+                        let mut digits = String::new();
+                        while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+                            digits.push(c);
+                            chars.next();
+                        }
+                        Json::Number(
+                            digits
+                                .parse()
+                                .map_err(|_| format!("`{}` is too large", digits))?,
+                        )
+                    }
+                    _ => return Err(format!("expected a value for `{}`", key)),
+                };
+                fields.push((key, value));
+
+                match skip_whitespace(&mut chars) {
+                    Some(',') => {
+                        chars.next();
+                    }
+                    Some('}') => {
+                        chars.next();
+                        break;
+                    }
+                    _ => return Err("expected `,` or `}`".to_string()),
+                }
+            }
+        }
+
+        match skip_whitespace(&mut chars) {
+            None => Ok(JsonObject(fields)),
+            Some(c) => Err(format!("unexpected `{}` after the object", c)),
+        }
+    }
+}
+
+/// Skips whitespace and returns the next character.
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+        chars.next();
+    }
+    chars.peek().copied()
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> std::result::Result<(), String> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        _ => Err(format!("expected `{}`", expected)),
+    }
+}
+
+fn json_string_literal(chars: &mut Peekable<Chars>) -> std::result::Result<String, String> {
+    expect(chars, '"')?;
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape `\\u{}`", code))?;
+                    s.push(c);
+                }
+                Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                _ => return Err("invalid escape in a string".to_string()),
+            },
+            Some(c) => s.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+/// Where two traces first differ.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    /// The number of entries both traces share before they differ.
+    pub index: usize,
+
+    /// The entries at `index`, or `None` for a trace that ends there.
+    pub expected: Option<Entry>,
+    pub actual: Option<Entry>,
+}
+
+/// Finds the first entry where `actual` differs from `expected`, see
+/// `Entry::matches`.
+pub fn diff(expected: &[Entry], actual: &[Entry]) -> Option<Divergence> {
+    let index = expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| !expected.matches(actual))
+        .unwrap_or_else(|| expected.len().min(actual.len()));
+
+    if index == expected.len() && index == actual.len() {
+        return None;
+    }
+
+    Some(Divergence {
+        index,
+        expected: expected.get(index).cloned(),
+        actual: actual.get(index).cloned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::assemble_items,
+        parser::parse_file,
+        sim::{Machine, MachineOptions},
+    };
+
+    fn trace(source: &str) -> Vec<Entry> {
+        let program = assemble_items(parse_file(source).unwrap()).unwrap();
+        let mut machine = Machine::with_program(&program, MachineOptions::default()).unwrap();
+
+        let mut entries = Vec::new();
+        machine
+            .run_traced(
+                100,
+                |_| false,
+                |cycle, step| entries.push(Entry::new(cycle, step, &program)),
+            )
+            .unwrap();
+        entries
+    }
+
+    const PROGRAM: &str = "LDI r0, #16\nLDI r1, #-1\nST r1, (r0)\n:end\nJMP end";
+
+    #[test]
+    fn format() {
+        let entries = trace(PROGRAM);
+        let lines = |format: Format| -> Vec<String> {
+            entries.iter().map(|entry| format.format(entry)).collect()
+        };
+
+        assert_eq!(
+            lines(Format::Text),
+            vec![
+                "0 0000 0100000000010000 r0=0010 ; LDI r0, #16",
+                "1 0001 0100000111111111 r1=ffff ; LDI r1, #-1",
+                "2 0002 0000000100001000 [0010]=ffff ; ST r1, (r0)",
+                "3 0003 1010000000000000 - ; JMP end",
+            ]
+        );
+        assert_eq!(
+            lines(Format::Csv)[2],
+            "2,0002,0000000100001000,[0010]=ffff,\"ST r1, (r0)\""
+        );
+        assert_eq!(Format::Csv.header(), Some("cycle,pc,code,write,disasm"));
+        assert_eq!(
+            lines(Format::Json)[..2],
+            [
+                r#"{"cycle":0,"pc":"0000","code":"0100000000010000","write":"r0=0010","disasm":"LDI r0, #16"}"#,
+                r#"{"cycle":1,"pc":"0001","code":"0100000111111111","write":"r1=ffff","disasm":"LDI r1, #-1"}"#,
+            ]
+        );
+        assert_eq!(
+            lines(Format::Json)[3],
+            r#"{"cycle":3,"pc":"0003","code":"1010000000000000","write":null,"disasm":"JMP end"}"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = trace(PROGRAM);
+        for format in [Format::Text, Format::Csv, Format::Json].iter() {
+            let text: Vec<String> = format
+                .header()
+                .map(str::to_string)
+                .into_iter()
+                .chain(entries.iter().map(|entry| format.format(entry)))
+                .collect();
+            assert_eq!(parse("trace", &text.join("\n")).unwrap(), entries);
+        }
+    }
+
+    #[test]
+    fn parse_testbench() {
+        // A log as a testbench would print it, without the disassembly.
+        let text = "\
+            // reset\n\
+            \n\
+            5 0 0100000000010000 r0=10\n\
+            6 1 0100_0001_1111_1111 r1=FFFF\n\
+            7,2,0000000100001000,[10]=ffff\n\
+            {\"cycle\": 8, \"pc\": \"3\", \"code\": \"1010000000000000\", \"write\": null}\n";
+        let entries = parse("tb.log", text).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries
+            .iter()
+            .zip(trace(PROGRAM))
+            .all(|(actual, expected)| actual.matches(&expected)));
+        assert_eq!(entries[0].cycle, 5);
+        assert_eq!(entries[0].disasm, "");
+
+        let reason = |text: &str| match parse("tb.log", text) {
+            Err(Error::InvalidTrace { file, line, reason }) => {
+                assert_eq!((file.as_str(), line), ("tb.log", 1));
+                reason
+            }
+            result => panic!("expected an invalid trace error, got {:?}", result),
+        };
+        assert_eq!(reason("0 0000 0100"), "expected 4 fields, found 3");
+        assert_eq!(
+            reason("0 0000 0100 -"),
+            "`0100` is not a 16-bit binary word"
+        );
+        assert_eq!(
+            reason("0 0000 0000000000000000 r8=0001"),
+            "`r8` is not a register"
+        );
+        assert_eq!(
+            reason("0 10000 0000000000000000 -"),
+            "`10000` is not a hexadecimal word"
+        );
+        assert_eq!(
+            reason(r#"{"cycle":0,"pc":"0","write":null}"#),
+            "expected a string `code`"
+        );
+        assert_eq!(reason(r#"{"cycle":0"#), "expected `,` or `}`");
+    }
+
+    #[test]
+    fn divergence() {
+        let expected = trace(PROGRAM);
+        assert_eq!(diff(&expected, &expected), None);
+
+        let mut actual = expected.clone();
+        for entry in actual.iter_mut() {
+            entry.cycle += 10;
+            entry.disasm.clear();
+        }
+        assert_eq!(diff(&expected, &actual), None);
+
+        actual[2].write = Some(Write::Memory(0x10, 0xfffe));
+        assert_eq!(
+            diff(&expected, &actual),
+            Some(Divergence {
+                index: 2,
+                expected: Some(expected[2].clone()),
+                actual: Some(actual[2].clone()),
+            })
+        );
+
+        assert_eq!(
+            diff(&expected, &expected[..3]),
+            Some(Divergence {
+                index: 3,
+                expected: Some(expected[3].clone()),
+                actual: None,
+            })
+        );
+    }
+}